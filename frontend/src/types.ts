@@ -6,16 +6,33 @@ type socketData =
   | browserPage
   | managementPage
   | globalSettings
+  | historyData
+  | sessionsPage
+  | tokensPage
+  | auditPage
+  | recordingsPage
+  | alertMessage
   | reauthenticate;
 
 interface statisticsPage {
   dataKind: "STATISTIC";
   cpu: number;
+  cpu_detail: cpuDetail;
   ram: usage;
   swap: usage;
+  memory: memoryDetail;
+  pressure: {
+    cpu: pressure | null;
+    memory: pressure | null;
+    io: pressure | null;
+  };
   disk: usage;
+  filesystems: filesystem[];
+  disk_io: diskIo[];
   network: net;
+  nics: nic[];
   temp: temp;
+  sensors: sensors;
 }
 
 interface softwarePage {
@@ -64,6 +81,34 @@ interface globalSettings {
   temp_unit: "fahrenheit" | "celsius";
 }
 
+interface historyData {
+  dataKind: "HISTORY";
+  range: number;
+  points: historyPoint[];
+}
+
+interface sessionsPage {
+  dataKind: "SESSIONS";
+  sessions: sessionItem[];
+}
+
+interface tokensPage {
+  dataKind: "TOKENS";
+  tokens: tokenItem[];
+  created: string | null;
+}
+
+interface auditPage {
+  dataKind: "AUDIT";
+  entries: auditItem[];
+}
+
+interface recordingsPage {
+  dataKind: "RECORDINGS";
+  recordings: recordingItem[];
+  open: { name: string; data: string } | null;
+}
+
 interface reauthenticate {
   dataKind: "REAUTH";
 }
@@ -100,6 +145,150 @@ interface browserItem {
   size: number;
 }
 
+interface historyPoint {
+  time: number;
+  cpu: number;
+  ram: number;
+  swap: number;
+  disk: number;
+  sent: number;
+  received: number;
+  temp: number | null;
+}
+
+interface sessionItem {
+  id: string;
+  user: string;
+  role: "viewer" | "operator" | "admin";
+  addr: string;
+  user_agent: string;
+  issued: number;
+  expires: number;
+}
+
+interface tokenItem {
+  name: string;
+  scopes: string[];
+  created: number;
+  expires: number | null;
+}
+
+interface alertMessage {
+  dataKind: "ALERT";
+  id: string;
+  time: number;
+  title: string;
+  message: string;
+  firing: boolean;
+}
+
+interface terminalItem {
+  id: string;
+  name: string;
+  user: string | null;
+  addr: string;
+  created: number;
+  clients: number;
+  idle: number | null;
+  viewers: { user: string | null; addr: string; since: number }[];
+}
+
+interface recordingItem {
+  name: string;
+  title: string;
+  user: string | null;
+  addr: string;
+  time: number;
+  duration: number;
+  size: number;
+}
+
+interface auditItem {
+  time: number;
+  user: string | null;
+  session: string | null;
+  token: string | null;
+  addr: string;
+  action: string;
+  args: string[];
+  success: boolean;
+  error: string | null;
+}
+
+interface cpuDetail {
+  cores: {
+    usage: number;
+    freq: { current: number; min: number; max: number } | null;
+  }[];
+  times: { user: number; system: number; iowait: number; steal: number };
+  load: { one: number; five: number; fifteen: number };
+  ctx_switches: number;
+  interrupts: number;
+}
+
+interface memoryDetail {
+  available: number;
+  buffers: number;
+  cached: number;
+  shared: number;
+  dirty: number;
+  swap_cached: number;
+  zswap: { compressed: number; original: number } | null;
+  zram: { name: string; original: number; compressed: number; used: number }[];
+  huge_pages: { total: number; free: number; size: number };
+  oom_kills: number | null;
+}
+
+interface pressureAvg {
+  avg10: number;
+  avg60: number;
+  avg300: number;
+}
+
+interface pressure {
+  some: pressureAvg;
+  full: pressureAvg | null;
+}
+
+interface filesystem {
+  device: string;
+  mountpoint: string;
+  fstype: string;
+  usage: usage;
+  inodes: usage;
+}
+
+interface diskIo {
+  name: string;
+  read_bytes: number;
+  write_bytes: number;
+  read_iops: number;
+  write_iops: number;
+  util: number;
+  wait: number;
+}
+
+interface nic {
+  name: string;
+  physical: boolean;
+  state: string;
+  speed: number | null;
+  duplex: string | null;
+  mtu: number | null;
+  mac: string | null;
+  addrs: string[];
+  rates: {
+    bytes_sent: number;
+    bytes_received: number;
+    packets_sent: number;
+    packets_received: number;
+    errors_in: number;
+    errors_out: number;
+    drops_in: number;
+    drops_out: number;
+  };
+}
+
 interface usage {
   used: number;
   total: number;
@@ -111,6 +300,28 @@ interface net {
   received: number;
 }
 
+interface sensors {
+  temps: {
+    source: "hwmon" | "thermal_zone";
+    name: string;
+    label: string | null;
+    current: number;
+    high: number | null;
+    critical: number | null;
+  }[];
+  fans: { name: string; label: string | null; rpm: number }[];
+  throttled: {
+    under_voltage: boolean;
+    freq_capped: boolean;
+    throttled: boolean;
+    soft_temp_limit: boolean;
+    under_voltage_occurred: boolean;
+    freq_capped_occurred: boolean;
+    throttled_occurred: boolean;
+    soft_temp_limit_occurred: boolean;
+  } | null;
+}
+
 interface temp {
   temp: number | null;
 }
@@ -125,6 +336,13 @@ export type {
   browserPage,
   managementPage,
   globalSettings,
+  historyData,
+  sessionsPage,
+  tokensPage,
+  auditPage,
+  recordingsPage,
+  alertMessage,
+  terminalItem,
   browserItem,
   processItem,
 };
@@ -1,15 +1,25 @@
 import type {
+  auditPage,
   browserPage,
+  historyData,
   managementPage,
   processPage,
+  recordingsPage,
   servicesPage,
+  sessionsPage,
   socketData,
   softwarePage,
   statisticsPage,
+  tokensPage,
 } from "./types";
 import { derived } from "svelte/store";
 
-type request = { page: string } | { cmd: string; args?: string[] } | { token: string };
+type request =
+  | { page: string }
+  | { cmd: string; args?: string[] }
+  | { token: string }
+  | { range: number }
+  | { interval: number };
 
 // Inspired by the svelte-websocket-store package
 function createWebsocketStore(host: string) {
@@ -101,8 +111,13 @@ export const socket = createWebsocketStore(window.location.host);
 
 // Creates a derived store that filters the WebSocket data by `dataKind`
 function createStore<T extends socketData>(defaultValue: T) {
+  let lastValue = defaultValue;
   const store = derived(socket, $socket => {
-    let lastValue = defaultValue;
+    // Alerts can come in on any page, and shouldn't clear its data
+    if ($socket && $socket.dataKind === "ALERT") {
+      return lastValue;
+    }
+    lastValue = defaultValue;
     if ($socket && $socket.dataKind == defaultValue.dataKind) {
       lastValue = $socket as T;
     }
@@ -115,11 +130,35 @@ function createStore<T extends socketData>(defaultValue: T) {
 export const statisticsStore = createStore<statisticsPage>({
   dataKind: "STATISTIC",
   cpu: 0,
+  cpu_detail: {
+    cores: [],
+    times: { user: 0, system: 0, iowait: 0, steal: 0 },
+    load: { one: 0, five: 0, fifteen: 0 },
+    ctx_switches: 0,
+    interrupts: 0,
+  },
   disk: { used: 0, total: 0, percent: 0 },
+  filesystems: [],
+  disk_io: [],
   ram: { used: 0, total: 0, percent: 0 },
   network: { sent: 0, received: 0 },
+  nics: [],
   swap: { used: 0, total: 0, percent: 0 },
+  memory: {
+    available: 0,
+    buffers: 0,
+    cached: 0,
+    shared: 0,
+    dirty: 0,
+    swap_cached: 0,
+    zswap: null,
+    zram: [],
+    huge_pages: { total: 0, free: 0, size: 0 },
+    oom_kills: null,
+  },
+  pressure: { cpu: null, memory: null, io: null },
   temp: { temp: null },
+  sensors: { temps: [], fans: [], throttled: null },
 });
 
 export const processStore = createStore<processPage>({
@@ -156,3 +195,31 @@ export const browserStore = createStore<browserPage>({
   contents: [],
   textdata: "",
 });
+
+export const historyStore = createStore<historyData>({
+  dataKind: "HISTORY",
+  range: 0,
+  points: [],
+});
+
+export const sessionsStore = createStore<sessionsPage>({
+  dataKind: "SESSIONS",
+  sessions: [],
+});
+
+export const tokensStore = createStore<tokensPage>({
+  dataKind: "TOKENS",
+  tokens: [],
+  created: null,
+});
+
+export const auditStore = createStore<auditPage>({
+  dataKind: "AUDIT",
+  entries: [],
+});
+
+export const recordingsStore = createStore<recordingsPage>({
+  dataKind: "RECORDINGS",
+  recordings: [],
+  open: null,
+});
@@ -0,0 +1,256 @@
+use std::collections::HashSet;
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tracing::instrument;
+
+use crate::config::AlertRule;
+use crate::shared::{self, CONFIG};
+use crate::{notifiers, samplers, systemdata};
+
+// Alerts that haven't been resolved yet, for clients that connect later
+static ACTIVE: Mutex<Vec<shared::Alert>> = Mutex::new(Vec::new());
+static SENDER: LazyLock<broadcast::Sender<shared::Alert>> =
+    LazyLock::new(|| broadcast::channel(16).0);
+
+fn fire(id: String, title: &str, message: String, firing: bool) {
+    let alert = shared::Alert {
+        id,
+        time: jsonwebtoken::get_current_timestamp(),
+        title: title.to_string(),
+        message,
+        firing,
+    };
+    if firing {
+        tracing::warn!("Alert {}: {}", alert.title, alert.message);
+    } else {
+        tracing::info!("Resolved {}: {}", alert.title, alert.message);
+    }
+
+    let mut active = ACTIVE.lock().unwrap_or_else(PoisonError::into_inner);
+    active.retain(|x| x.id != alert.id);
+    if firing {
+        active.push(alert.clone());
+    }
+    drop(active);
+    // Only fails without any connected clients
+    let _ = SENDER.send(alert.clone());
+    notifiers::deliver(alert);
+}
+
+// Sends alerts to one socket, starting with the ones that are still going on
+pub async fn forward(socket_send: shared::SocketSend) {
+    let mut receiver = SENDER.subscribe();
+    let active = ACTIVE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    for alert in active {
+        if socket_send
+            .send(shared::BackendData::Alert(alert))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+    loop {
+        match receiver.recv().await {
+            Ok(alert) => {
+                if socket_send
+                    .send(shared::BackendData::Alert(alert))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+fn known_metric(metric: &str) -> bool {
+    matches!(
+        metric,
+        "cpu"
+            | "ram"
+            | "swap"
+            | "disk"
+            | "temp"
+            | "load1"
+            | "load5"
+            | "load15"
+            | "iowait"
+            | "steal"
+            | "pressure_cpu"
+            | "pressure_memory"
+            | "pressure_io"
+    ) || metric
+        .split_once(':')
+        .is_some_and(|(kind, _)| matches!(kind, "filesystem" | "inodes" | "disk_util"))
+}
+
+// None if the metric isn't there right now, like an unmounted filesystem
+fn metric(metric: &str, data: &shared::SysData) -> Option<f64> {
+    let detail = &data.cpu_detail;
+    let pressure = |pressure: Option<&shared::Pressure>| Some(f64::from(pressure?.some.avg10));
+    Some(match metric {
+        "cpu" => f64::from(data.cpu),
+        "ram" => f64::from(data.ram.percent),
+        "swap" => f64::from(data.swap.percent),
+        "disk" => f64::from(data.disk.percent),
+        "temp" => f64::from(data.temp.temp?),
+        "load1" => detail.load.one,
+        "load5" => detail.load.five,
+        "load15" => detail.load.fifteen,
+        "iowait" => f64::from(detail.times.iowait),
+        "steal" => f64::from(detail.times.steal),
+        "pressure_cpu" => pressure(data.pressure.cpu.as_ref())?,
+        "pressure_memory" => pressure(data.pressure.memory.as_ref())?,
+        "pressure_io" => pressure(data.pressure.io.as_ref())?,
+        _ => {
+            let (kind, name) = metric.split_once(':')?;
+            match kind {
+                "filesystem" | "inodes" => {
+                    let filesystem = data.filesystems.iter().find(|x| x.mountpoint == name)?;
+                    f64::from(if kind == "filesystem" {
+                        filesystem.usage.percent
+                    } else {
+                        filesystem.inodes.percent
+                    })
+                }
+                "disk_util" => f64::from(data.disk_io.iter().find(|x| x.name == name)?.util),
+                _ => return None,
+            }
+        }
+    })
+}
+
+#[derive(Default)]
+struct RuleState {
+    // When the condition started to hold
+    since: Option<Instant>,
+    firing: bool,
+    last_fired: Option<Instant>,
+}
+
+fn check(index: usize, rule: &AlertRule, state: &mut RuleState, data: &shared::SysData) {
+    let now = Instant::now();
+    let id = format!("rule:{index}");
+    let title = if rule.name.is_empty() {
+        &rule.metric
+    } else {
+        &rule.name
+    };
+    if let Some(value) =
+        metric(&rule.metric, data).filter(|&x| rule.comparator.compare(x, rule.threshold))
+    {
+        let since = *state.since.get_or_insert(now);
+        if !state.firing
+            && now - since >= Duration::from_secs(rule.duration)
+            && state
+                .last_fired
+                .is_none_or(|x| now - x >= Duration::from_secs(rule.cooldown))
+        {
+            state.firing = true;
+            state.last_fired = Some(now);
+            fire(
+                id,
+                title,
+                format!(
+                    "{} is {} (alerting when {} {})",
+                    rule.metric,
+                    (value * 100.0).round() / 100.0,
+                    rule.comparator.as_str(),
+                    rule.threshold
+                ),
+                true,
+            );
+        }
+    } else {
+        state.since = None;
+        if state.firing {
+            state.firing = false;
+            fire(
+                id,
+                title,
+                format!("{} is back to normal", rule.metric),
+                false,
+            );
+        }
+    }
+}
+
+// Checks every new sample of statistics against the rules
+// Like the history, that keeps the statistics sampler running at least every background_interval
+#[instrument(skip_all)]
+pub async fn rules() {
+    for rule in &CONFIG.alerts {
+        if !known_metric(&rule.metric) {
+            tracing::warn!(
+                "Unknown metric {} in alert rule, it will never alert",
+                rule.metric
+            );
+        }
+    }
+    let mut states = CONFIG
+        .alerts
+        .iter()
+        .map(|_| RuleState::default())
+        .collect::<Vec<_>>();
+    let mut statistics = samplers::background();
+    while let Some(data) = statistics.next().await {
+        for (index, (rule, state)) in CONFIG.alerts.iter().zip(&mut states).enumerate() {
+            check(index, rule, state, &data);
+        }
+    }
+}
+
+// Alerts when services fail, and again when they're running again
+#[instrument(skip_all)]
+pub async fn services() {
+    let mut interval = tokio::time::interval(Duration::from_secs(CONFIG.alert_service_interval));
+    let mut failed = HashSet::new();
+    let mut first = true;
+    loop {
+        interval.tick().await;
+        let services = match systemdata::services().await {
+            Ok(services) => services,
+            // Like when not running on DietPi, or not as root
+            Err(err) if first => {
+                tracing::warn!("Not alerting on failed services: {:#}", err);
+                return;
+            }
+            Err(err) => {
+                tracing::warn!("{:#}", err);
+                continue;
+            }
+        };
+        first = false;
+        let now_failed = services
+            .into_iter()
+            .filter(|x| x.status == "failed")
+            .map(|x| x.name)
+            .collect::<HashSet<_>>();
+        for name in now_failed.difference(&failed) {
+            fire(
+                format!("service:{name}"),
+                &format!("Service {name}"),
+                format!("Service {name} has failed"),
+                true,
+            );
+        }
+        for name in failed.difference(&now_failed) {
+            fire(
+                format!("service:{name}"),
+                &format!("Service {name}"),
+                format!("Service {name} isn't failed anymore"),
+                false,
+            );
+        }
+        failed = now_failed;
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::Context;
+use hyper::http::header;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::{
+    audit, handle_error, page_handlers, samplers, shared, socket_handlers, systemdata, CONFIG,
+};
+
+fn json_response(status: StatusCode, value: &impl Serialize) -> anyhow::Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(
+            serde_json::to_string(value)
+                .context("Couldn't serialize JSON")?
+                .into(),
+        )?)
+}
+
+fn error_response(status: StatusCode, error: impl Into<String>) -> anyhow::Result<Response<Body>> {
+    json_response(
+        status,
+        &shared::ApiError {
+            error: error.into(),
+        },
+    )
+}
+
+// Turn a handler result into a response, so errors are reported to the client instead of only being logged
+fn respond(result: anyhow::Result<impl Serialize>) -> anyhow::Result<Response<Body>> {
+    match result {
+        Ok(value) => json_response(StatusCode::OK, &value),
+        Err(err) => {
+            tracing::warn!("{:#}", err);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}"))
+        }
+    }
+}
+
+// Actions don't return anything, so just report success or failure
+fn respond_action(result: anyhow::Result<()>) -> anyhow::Result<Response<Body>> {
+    match result {
+        Ok(()) => Ok(Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())?),
+        Err(err) => respond(Err::<(), _>(err)),
+    }
+}
+
+fn get_query_param(req: &Request<Body>, name: &str) -> anyhow::Result<Option<String>> {
+    req.uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|x| x.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| shared::percent_decode(value))
+        .transpose()
+}
+
+// Latest sample from the statistics page, which has to wait for one if nobody's looking at it
+async fn system() -> anyhow::Result<shared::SysData> {
+    samplers::statistics()
+        .next()
+        .await
+        .context("Statistics sampler stopped")
+}
+
+async fn software() -> anyhow::Result<shared::DPSoftwareList> {
+    let software = systemdata::dpsoftware().await?;
+    Ok(shared::DPSoftwareList {
+        uninstalled: software.0,
+        installed: software.1,
+        response: None,
+    })
+}
+
+async fn files(req: &Request<Body>) -> anyhow::Result<shared::BrowserList> {
+    let path = get_query_param(req, "path")?
+        .unwrap_or_else(|| std::env::var("HOME").unwrap_or_else(|_| "/root".to_string()));
+    Ok(shared::BrowserList {
+        contents: systemdata::browser_dir(std::path::Path::new(&path)).await?,
+    })
+}
+
+async fn software_action(
+    mut req: Request<Body>,
+    action: &str,
+    actor: &audit::Actor,
+) -> anyhow::Result<Response<Body>> {
+    let body = hyper::body::to_bytes(req.body_mut())
+        .await
+        .context("Couldn't read request body")?;
+    let Ok(ids) = serde_json::from_slice::<Vec<u16>>(&body) else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Body must be a JSON list of software IDs",
+        );
+    };
+    let ids = ids.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let result = page_handlers::software_handler_helper(action, &ids).await;
+    respond(audit::record(
+        actor,
+        &format!("software.{action}"),
+        &ids,
+        result,
+    ))
+}
+
+fn audit_filter(req: &Request<Body>) -> anyhow::Result<audit::Filter> {
+    let param = |name| get_query_param(req, name).map(Option::unwrap_or_default);
+    Ok(audit::Filter {
+        user: param("user")?,
+        action: param("action")?,
+        outcome: param("outcome")?,
+        text: param("text")?,
+    })
+}
+
+// Returns what the client may do and who they are, or None if they aren't logged in
+fn get_access(
+    req: &Request<Body>,
+    remote_ip: std::net::IpAddr,
+) -> Option<(shared::Access, audit::Actor)> {
+    if !CONFIG.pass {
+        return Some((
+            shared::Access::Role(shared::Role::Admin),
+            audit::Actor::anonymous(remote_ip),
+        ));
+    }
+    let token = shared::get_bearer_token(req)?;
+    let fingerprint = handle_error!(shared::get_fingerprint(req), return None);
+    let validation = socket_handlers::validate_token(token, fingerprint.as_deref());
+    if let socket_handlers::TokenState::ApiToken { name, .. } = &validation {
+        tracing::info!("Using API token {}", name);
+    }
+    Some((validation.access()?, validation.actor(remote_ip)))
+}
+
+#[instrument(skip_all)]
+pub async fn api_route(
+    req: Request<Body>,
+    remote_ip: std::net::IpAddr,
+) -> anyhow::Result<Response<Body>> {
+    let Some((access, actor)) = get_access(&req, remote_ip) else {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(r#"{"error":"Invalid token"}"#.into())?);
+    };
+
+    let path = req.uri().path().trim_end_matches('/').to_string();
+    let Some(path) = path.strip_prefix("/api/v1/") else {
+        return error_response(StatusCode::NOT_FOUND, "Unknown API endpoint");
+    };
+    let segments = path.split('/').collect::<Vec<_>>();
+
+    // Actions need the same role or scope as on the matching page
+    let allowed = match (req.method(), segments.as_slice()) {
+        (&Method::POST, ["processes", _, action]) => access.can_run("/process", action),
+        (&Method::POST, ["services", _, action]) => access.can_run("/service", action),
+        (&Method::POST, ["software", action]) => access.can_run("/software", action),
+        (_, ["sessions", ..]) => access.can_view("/sessions"),
+        (_, ["audit"]) => access.can_view("/audit"),
+        (_, ["terminals", ..]) => access.can_run("/terminal", ""),
+        (&Method::GET, ["files"]) => access.can_run("/browser", "cd"),
+        (&Method::POST, _) => matches!(access, shared::Access::Role(shared::Role::Admin)),
+        _ => access.can_view("/"),
+    };
+    if !allowed {
+        return error_response(StatusCode::FORBIDDEN, "Not allowed");
+    }
+
+    match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["system"]) => respond(system().await),
+        (&Method::GET, ["processes"]) => respond(
+            samplers::processes()
+                .next()
+                .await
+                .map(|processes| shared::ProcessList { processes })
+                .context("Process sampler stopped"),
+        ),
+        (&Method::GET, ["services"]) => respond(
+            systemdata::services()
+                .await
+                .map(|services| shared::ServiceList { services }),
+        ),
+        (&Method::GET, ["software"]) => respond(software().await),
+        (&Method::GET, ["host"]) => respond(systemdata::host().await),
+        (&Method::GET, ["files"]) => respond(files(&req).await),
+        (
+            &Method::POST,
+            ["processes", pid, action @ ("terminate" | "kill" | "suspend" | "resume")],
+        ) => respond_action(audit::record(
+            &actor,
+            &format!("process.{action}"),
+            &[pid],
+            page_handlers::process_handler_helper(action, Some(pid)),
+        )),
+        (&Method::POST, ["services", name, action @ ("start" | "stop" | "restart")]) => {
+            let name = shared::percent_decode(name)?;
+            let result = page_handlers::service_handler_helper(action, &name).await;
+            respond_action(audit::record(
+                &actor,
+                &format!("service.{action}"),
+                &[&name],
+                result,
+            ))
+        }
+        (&Method::POST, ["software", action @ ("install" | "uninstall" | "reinstall")]) => {
+            software_action(req, action, &actor).await
+        }
+        (&Method::GET, ["sessions"]) => respond(Ok(shared::SessionList {
+            sessions: crate::sessions::list(),
+        })),
+        (&Method::POST, ["sessions", id, "revoke"]) => {
+            if crate::sessions::revoke(id) {
+                respond_action(audit::record(&actor, "session.revoke", &[id], Ok(())))
+            } else {
+                error_response(StatusCode::NOT_FOUND, "No such session")
+            }
+        }
+        (&Method::GET, ["terminals"]) => respond(Ok(shared::TerminalList {
+            terminals: crate::terminals::list(),
+        })),
+        (&Method::POST, ["terminals", id, "kill"]) => {
+            if crate::terminals::kill(id) {
+                respond_action(audit::record(&actor, "terminal.kill", &[id], Ok(())))
+            } else {
+                error_response(StatusCode::NOT_FOUND, "No such terminal")
+            }
+        }
+        (&Method::GET, ["audit"]) => respond(
+            audit_filter(&req)
+                .and_then(|filter| audit::query(&filter))
+                .map(|entries| shared::AuditList { entries }),
+        ),
+        (&Method::GET | &Method::POST, _) => {
+            error_response(StatusCode::NOT_FOUND, "Unknown API endpoint")
+        }
+        _ => error_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::Context;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+use subtle::ConstantTimeEq;
+
+use crate::shared::{ApiTokenInfo, Scope};
+use crate::{config, handle_error};
+
+// Lets API tokens be told apart from login tokens, which always contain dots
+pub const PREFIX: &str = "dpd_";
+const FILE: &str = "api_tokens.json";
+
+#[derive(Serialize, Deserialize)]
+struct ApiToken {
+    name: String,
+    // SHA-256, which is enough since the tokens are random
+    hash: String,
+    scopes: Vec<Scope>,
+    created: u64,
+    expires: Option<u64>,
+}
+
+// Held while the file is changed
+static LOCK: Mutex<()> = Mutex::new(());
+
+// Tokens as last read, with the modification time of the file back then (None if it didn't exist)
+// Tokens are checked for every API call and socket message, so the file is only read again when it has changed
+type Cached = (Option<SystemTime>, Arc<Vec<ApiToken>>);
+static CACHE: Mutex<Option<Cached>> = Mutex::new(None);
+
+fn load() -> anyhow::Result<Vec<ApiToken>> {
+    let path = config::data_path(FILE);
+    match std::fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .with_context(|| format!("Couldn't parse API token file {}", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => {
+            Err(err).with_context(|| format!("Couldn't read API token file {}", path.display()))
+        }
+    }
+}
+
+fn cached() -> anyhow::Result<Arc<Vec<ApiToken>>> {
+    let modified = std::fs::metadata(config::data_path(FILE))
+        .and_then(|x| x.modified())
+        .ok();
+    let mut cache = CACHE.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some((time, tokens)) = &*cache {
+        if *time == modified {
+            return Ok(Arc::clone(tokens));
+        }
+    }
+    let tokens = Arc::new(load()?);
+    *cache = Some((modified, Arc::clone(&tokens)));
+    drop(cache);
+    Ok(tokens)
+}
+
+fn save(tokens: &[ApiToken]) -> anyhow::Result<()> {
+    let data = serde_json::to_vec(tokens).context("Couldn't serialize API tokens")?;
+    let result = config::write_private(FILE, &data);
+    // The modification time might not change if it's written twice in quick succession
+    *CACHE.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    result
+}
+
+fn hash(token: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+// Expiry in days from now, 0 meaning never
+pub fn expiry_from_days(days: u64) -> Option<u64> {
+    (days != 0).then(|| jsonwebtoken::get_current_timestamp() + days * 24 * 60 * 60)
+}
+
+// Returns the token, which is only ever shown this once
+pub fn create(name: &str, scopes: &[Scope], expires: Option<u64>) -> anyhow::Result<String> {
+    anyhow::ensure!(!name.is_empty(), "API token name can't be empty");
+    anyhow::ensure!(!scopes.is_empty(), "API tokens need at least one scope");
+
+    let _guard = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut tokens = load()?;
+    anyhow::ensure!(
+        tokens.iter().all(|x| x.name != name),
+        "There's already an API token called {name}"
+    );
+
+    let mut buf = [0u8; 32];
+    getrandom::getrandom(&mut buf).context("Couldn't generate random API token")?;
+    let token = format!("{PREFIX}{}", hex::encode(buf));
+
+    let mut unique = Vec::new();
+    for scope in scopes {
+        if !unique.contains(scope) {
+            unique.push(*scope);
+        }
+    }
+    tokens.push(ApiToken {
+        name: name.to_string(),
+        hash: hash(&token),
+        scopes: unique,
+        created: jsonwebtoken::get_current_timestamp(),
+        expires,
+    });
+    save(&tokens)?;
+    tracing::info!("Created API token {}", name);
+    Ok(token)
+}
+
+// Returns whether the token existed
+pub fn revoke(name: &str) -> anyhow::Result<bool> {
+    let _guard = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut tokens = load()?;
+    let len = tokens.len();
+    tokens.retain(|x| x.name != name);
+    if tokens.len() == len {
+        return Ok(false);
+    }
+    save(&tokens)?;
+    tracing::info!("Revoked API token {}", name);
+    Ok(true)
+}
+
+pub fn list() -> anyhow::Result<Vec<ApiTokenInfo>> {
+    Ok(load()?
+        .into_iter()
+        .map(|x| ApiTokenInfo {
+            name: x.name,
+            scopes: x.scopes,
+            created: x.created,
+            expires: x.expires,
+        })
+        .collect())
+}
+
+// Returns the token's name and scopes, if it's valid
+// Read again whenever the file changes, so tokens made or revoked on the command line count straight away
+pub fn validate(token: &str) -> Option<(String, Vec<Scope>)> {
+    let hash = hash(token);
+    let now = jsonwebtoken::get_current_timestamp();
+    handle_error!(cached(), return None)
+        .iter()
+        .find(|x| bool::from(x.hash.as_bytes().ct_eq(hash.as_bytes())))
+        .filter(|x| x.expires.is_none_or(|expires| expires > now))
+        .map(|x| (x.name.clone(), x.scopes.clone()))
+}
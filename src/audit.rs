@@ -0,0 +1,191 @@
+use anyhow::Context;
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::net::IpAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+use crate::config;
+use crate::shared::{AuditEntry, CONFIG};
+
+const FILE: &str = "audit.log";
+// Most entries sent to the dashboard at once
+const LIMIT: usize = 500;
+
+// Who is doing something, and from where
+#[derive(Clone, Debug)]
+pub struct Actor {
+    // None without a login
+    pub user: Option<String>,
+    pub session: Option<String>,
+    pub token: Option<String>,
+    pub addr: IpAddr,
+}
+
+impl Actor {
+    pub const fn anonymous(addr: IpAddr) -> Self {
+        Self {
+            user: None,
+            session: None,
+            token: None,
+            addr: addr.to_canonical(),
+        }
+    }
+}
+
+// Held while the log is written or rotated
+static LOCK: Mutex<()> = Mutex::new(());
+
+// "audit.log" for 0, "audit.log.1" and so on for rotated files
+fn path(index: u32) -> PathBuf {
+    if index == 0 {
+        config::data_path(FILE)
+    } else {
+        config::data_path(&format!("{FILE}.{index}"))
+    }
+}
+
+fn rotate() -> anyhow::Result<()> {
+    let max_size = CONFIG.audit_max_size * 1024 * 1024;
+    let size = match std::fs::metadata(path(0)) {
+        Ok(meta) => meta.len(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).context("Couldn't get size of audit log"),
+    };
+    if max_size == 0 || size < max_size {
+        return Ok(());
+    }
+    // The oldest file is overwritten, or just removed without any rotated files to keep
+    if CONFIG.audit_files == 0 {
+        return std::fs::remove_file(path(0)).context("Couldn't remove full audit log");
+    }
+    for index in (0..CONFIG.audit_files).rev() {
+        match std::fs::rename(path(index), path(index + 1)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err).with_context(|| {
+                    format!("Couldn't rotate audit log {}", path(index).display())
+                })
+            }
+            _ => {}
+        }
+    }
+    tracing::info!("Rotated audit log");
+    Ok(())
+}
+
+fn append(entry: &AuditEntry) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(entry).context("Couldn't serialize audit entry")?;
+    line.push(b'\n');
+
+    let _guard = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    rotate()?;
+    // Only ever appended to, and not readable by others since it has addresses in it
+    std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path(0))
+        .and_then(|mut file| file.write_all(&line))
+        .context("Couldn't write to audit log")
+}
+
+// Records an action and its outcome, and passes the outcome on
+// Failing to write the log doesn't undo the action, so that's only logged
+pub fn record<T>(
+    actor: &Actor,
+    action: &str,
+    args: &[impl AsRef<str>],
+    result: anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let entry = AuditEntry {
+        time: jsonwebtoken::get_current_timestamp(),
+        user: actor.user.clone(),
+        session: actor.session.clone(),
+        token: actor.token.clone(),
+        addr: actor.addr.to_string(),
+        action: action.to_string(),
+        args: args.iter().map(|x| x.as_ref().to_string()).collect(),
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|err| format!("{err:#}")),
+    };
+    if let Err(err) = append(&entry) {
+        tracing::error!("{:#}", err);
+    }
+    result
+}
+
+// Empty fields match everything
+#[derive(Default, Debug)]
+pub struct Filter {
+    pub user: String,
+    // Matches the start of the action, so "process" finds every process action
+    pub action: String,
+    // "success" or "failure"
+    pub outcome: String,
+    // Searched for in the address, arguments and error
+    pub text: String,
+}
+
+impl Filter {
+    pub fn from_args(args: &[String]) -> Self {
+        let arg = |index: usize| args.get(index).cloned().unwrap_or_default();
+        Self {
+            user: arg(0),
+            action: arg(1),
+            outcome: arg(2),
+            text: arg(3),
+        }
+    }
+
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        (self.user.is_empty()
+            || entry.user.as_deref() == Some(&self.user)
+            || entry.token.as_deref() == Some(&self.user))
+            && entry.action.starts_with(&self.action)
+            && match self.outcome.as_str() {
+                "success" => entry.success,
+                "failure" => !entry.success,
+                _ => true,
+            }
+            && (self.text.is_empty()
+                || entry.addr.contains(&self.text)
+                || entry.args.iter().any(|x| x.contains(&self.text))
+                || entry.error.as_ref().is_some_and(|x| x.contains(&self.text)))
+    }
+}
+
+// Newest matching entries first, going through rotated files until there's enough
+pub fn query(filter: &Filter) -> anyhow::Result<Vec<AuditEntry>> {
+    let mut entries = Vec::new();
+    for index in 0..=CONFIG.audit_files {
+        let file = match std::fs::File::open(path(index)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Couldn't open audit log {}", path(index).display()))
+            }
+        };
+        let mut matching = VecDeque::new();
+        for line in std::io::BufReader::new(file).lines() {
+            let line = line.context("Couldn't read audit log")?;
+            let Ok(entry) = serde_json::from_str::<AuditEntry>(&line) else {
+                tracing::debug!("Skipping invalid audit log line {}", line);
+                continue;
+            };
+            if filter.matches(&entry) {
+                // Only the newest entries of each file can be sent
+                if matching.len() == LIMIT - entries.len() {
+                    matching.pop_front();
+                }
+                matching.push_back(entry);
+            }
+        }
+        entries.extend(matching.into_iter().rev());
+        if entries.len() >= LIMIT {
+            break;
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,113 @@
+use anyhow::Context;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use ring::digest;
+use serde::Deserialize;
+use std::sync::LazyLock;
+use subtle::ConstantTimeEq;
+
+use crate::handle_error;
+use crate::shared::{Role, CONFIG};
+
+// Name given to the account that uses the old single "hash" setting
+pub const LEGACY_USER: &str = "admin";
+
+// Checked for unknown users, so that they take as long as real ones and can't be told apart
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| handle_error!(hash_password("", false), String::new()));
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    #[serde(default)]
+    pub username: String,
+    pub password: String,
+    // Either a TOTP code or a recovery code
+    #[serde(default)]
+    pub code: String,
+}
+
+impl LoginRequest {
+    // Older frontends send just the password as the body
+    pub fn parse(body: &[u8]) -> Self {
+        serde_json::from_slice(body).unwrap_or_else(|_| Self {
+            username: String::new(),
+            password: String::from_utf8_lossy(body).into_owned(),
+            code: String::new(),
+        })
+    }
+}
+
+// The hash format is picked from its prefix: PHC strings for Argon2, "$2" for bcrypt, and hex for SHA-512
+fn check_password(hash: &str, password: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        let hash = handle_error!(
+            PasswordHash::new(hash).context("Invalid Argon2 password hash"),
+            return false
+        );
+        argon2::Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    } else if hash.starts_with("$2") {
+        handle_error!(
+            bcrypt::verify(password, hash).context("Invalid bcrypt password hash"),
+            false
+        )
+    } else {
+        tracing::warn!(
+            "Unsalted SHA-512 password hashes are deprecated, use `dietpi-dashboard hash-password` to make a new one"
+        );
+        let expected = handle_error!(
+            hex::decode(hash).context("Invalid SHA-512 password hash"),
+            return false
+        );
+        digest::digest(&digest::SHA512, password.as_bytes())
+            .as_ref()
+            .ct_eq(&expected)
+            .into()
+    }
+}
+
+pub fn hash_password(password: &str, use_bcrypt: bool) -> anyhow::Result<String> {
+    if use_bcrypt {
+        return bcrypt::hash(password, bcrypt::DEFAULT_COST).context("Couldn't hash password");
+    }
+    let mut salt = [0u8; 16];
+    getrandom::getrandom(&mut salt).context("Couldn't generate random salt")?;
+    let salt = SaltString::encode_b64(&salt).context("Couldn't encode salt")?;
+    Ok(argon2::Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .context("Couldn't hash password")?
+        .to_string())
+}
+
+// Returns the user's name and role if the password is correct
+pub async fn authenticate(req: &LoginRequest) -> Option<(String, Role)> {
+    let username = if req.username.is_empty() {
+        LEGACY_USER
+    } else {
+        &req.username
+    };
+
+    let account = CONFIG
+        .users
+        .iter()
+        .find(|x| x.name == username)
+        .map(|user| (&user.hash, (user.name.clone(), user.role)))
+        // Only fall back to the single password if there's no users table entry with the same name
+        .or_else(|| {
+            (username == LEGACY_USER && !CONFIG.hash.is_empty())
+                .then(|| (&CONFIG.hash, (LEGACY_USER.to_string(), Role::Admin)))
+        });
+    let hash = account.as_ref().map(|(hash, _)| hash.as_str());
+    let password = req.password.clone();
+
+    // Hashing takes long on purpose, which would hold up everything else on the runtime
+    let correct = handle_error!(
+        tokio::task::spawn_blocking(move || {
+            check_password(hash.unwrap_or(&DUMMY_HASH), &password)
+        })
+        .await
+        .context("Couldn't check password"),
+        false
+    );
+    account.filter(|_| correct).map(|(_, user)| user)
+}
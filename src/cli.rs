@@ -0,0 +1,159 @@
+use anyhow::Context;
+use std::io::BufRead;
+
+use crate::shared::{Scope, CONFIG};
+use crate::{apitokens, totp};
+
+fn read_line(prompt: &str) -> anyhow::Result<String> {
+    eprint!("{prompt}");
+    let mut line = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("Couldn't read from stdin")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// Like read_line, but without showing what's typed
+fn read_password(prompt: &str) -> anyhow::Result<String> {
+    use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg};
+    use std::os::unix::io::AsRawFd;
+
+    let fd = std::io::stdin().as_raw_fd();
+    // Not a terminal, like when the password is piped in
+    let Ok(original) = tcgetattr(fd) else {
+        return read_line(prompt);
+    };
+    let mut hidden = original.clone();
+    hidden.local_flags.remove(LocalFlags::ECHO);
+    // Still end the line when Enter is pressed
+    hidden.local_flags.insert(LocalFlags::ECHONL);
+    tcsetattr(fd, SetArg::TCSANOW, &hidden).context("Couldn't turn off echo")?;
+    let password = read_line(prompt);
+    tcsetattr(fd, SetArg::TCSANOW, &original).context("Couldn't turn echo back on")?;
+    password
+}
+
+fn hash_password(args: &[String]) -> anyhow::Result<()> {
+    let use_bcrypt = match args {
+        [] => false,
+        [arg] if arg == "--bcrypt" => true,
+        _ => anyhow::bail!("Usage: dietpi-dashboard hash-password [--bcrypt]"),
+    };
+    let password = read_password("Password: ")?;
+    anyhow::ensure!(!password.is_empty(), "Password can't be empty");
+    println!("{}", crate::auth::hash_password(&password, use_bcrypt)?);
+    Ok(())
+}
+
+fn get_user(args: &[String], usage: &str) -> anyhow::Result<String> {
+    let user = match args {
+        [] => crate::auth::LEGACY_USER,
+        [user] => user,
+        _ => anyhow::bail!("Usage: dietpi-dashboard {usage} [user]"),
+    };
+    anyhow::ensure!(
+        user == crate::auth::LEGACY_USER || CONFIG.users.iter().any(|x| x.name == user),
+        "Unknown user {user}"
+    );
+    Ok(user.to_string())
+}
+
+fn totp_enroll(args: &[String]) -> anyhow::Result<()> {
+    let user = get_user(args, "totp-enroll")?;
+    if totp::enabled(&user)? {
+        eprintln!("Two-factor authentication is already enabled for {user}, enrolling again will replace it");
+    }
+
+    let enrollment = totp::NewEnrollment::new(&user)?;
+    eprintln!("Add this to your authenticator app:");
+    println!("{}", enrollment.uri);
+    // Only turn it on once the app is known to work, so nobody gets locked out
+    let code = read_line("Code from the app: ")?;
+    anyhow::ensure!(
+        enrollment.verify(&code),
+        "Incorrect code, nothing was changed"
+    );
+
+    eprintln!("Recovery codes, each can be used once instead of a code from the app:");
+    for code in &enrollment.recovery {
+        println!("{code}");
+    }
+    enrollment.save(&user)?;
+    eprintln!("Two-factor authentication enabled for {user}");
+    Ok(())
+}
+
+fn totp_remove(args: &[String]) -> anyhow::Result<()> {
+    let user = get_user(args, "totp-remove")?;
+    if totp::remove(&user)? {
+        eprintln!("Two-factor authentication disabled for {user}");
+    } else {
+        eprintln!("Two-factor authentication wasn't enabled for {user}");
+    }
+    Ok(())
+}
+
+fn token_create(args: &[String]) -> anyhow::Result<()> {
+    let usage = format!(
+        "Usage: dietpi-dashboard token-create <name> <scope,...> [days until expiry]\nScopes: {}",
+        Scope::ALL.map(Scope::as_str).join(", ")
+    );
+    let (name, scopes, days) = match args {
+        [name, scopes] => (name, scopes, 0),
+        [name, scopes, days] => (name, scopes, days.parse().context(usage.clone())?),
+        _ => anyhow::bail!(usage),
+    };
+    let scopes = scopes
+        .split(',')
+        .map(|x| Scope::parse(x.trim()).with_context(|| format!("Unknown scope {x}\n{usage}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let token = apitokens::create(name, &scopes, apitokens::expiry_from_days(days))?;
+    eprintln!("API token {name} created, it can't be shown again:");
+    println!("{token}");
+    Ok(())
+}
+
+fn token_list() -> anyhow::Result<()> {
+    for token in apitokens::list()? {
+        let scopes = token
+            .scopes
+            .iter()
+            .map(|x| x.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+        let expires = token
+            .expires
+            .map_or_else(|| "never".to_string(), |x| x.to_string());
+        println!(
+            "{}\t{}\tcreated {}\texpires {}",
+            token.name, scopes, token.created, expires
+        );
+    }
+    Ok(())
+}
+
+fn token_revoke(args: &[String]) -> anyhow::Result<()> {
+    let [name] = args else {
+        anyhow::bail!("Usage: dietpi-dashboard token-revoke <name>");
+    };
+    anyhow::ensure!(apitokens::revoke(name)?, "No API token called {name}");
+    eprintln!("API token {name} revoked");
+    Ok(())
+}
+
+// Subcommands run instead of the server, and exit once they're done
+pub fn run(cmd: &str, args: &[String]) -> anyhow::Result<()> {
+    match cmd {
+        "hash-password" => hash_password(args),
+        "totp-enroll" => totp_enroll(args),
+        "totp-remove" => totp_remove(args),
+        "token-create" => token_create(args),
+        "token-list" => token_list(),
+        "token-revoke" => token_revoke(args),
+        _ => anyhow::bail!(
+            "Unknown subcommand {cmd}, available: hash-password, totp-enroll, totp-remove, token-create, token-list, token-revoke"
+        ),
+    }
+}
@@ -1,11 +1,85 @@
-use crate::shared::TempUnit;
+use crate::shared::{Role, TempUnit};
+use anyhow::Context;
 use figment::{
     providers::{Env, Format, Serialized, Toml},
     Figment,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
 
 #[derive(Deserialize, Serialize)]
+pub struct User {
+    pub name: String,
+    pub hash: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub enum Comparator {
+    #[serde(rename = ">")]
+    Greater,
+    #[serde(rename = ">=")]
+    GreaterOrEqual,
+    #[serde(rename = "<")]
+    Less,
+    #[serde(rename = "<=")]
+    LessOrEqual,
+}
+
+impl Comparator {
+    pub fn compare(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Self::Greater => value > threshold,
+            Self::GreaterOrEqual => value >= threshold,
+            Self::Less => value < threshold,
+            Self::LessOrEqual => value <= threshold,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AlertRule {
+    // Shown in notifications, the metric is used without one
+    #[serde(default)]
+    pub name: String,
+    pub metric: String,
+    pub comparator: Comparator,
+    pub threshold: f64,
+    // Seconds that the condition has to hold before alerting
+    #[serde(default)]
+    pub duration: u64,
+    // Seconds before the same rule can alert again
+    #[serde(default = "default_cooldown")]
+    pub cooldown: u64,
+}
+
+const fn default_cooldown() -> u64 {
+    3600
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    // Plain connection upgraded with STARTTLS, usually on port 587
+    Starttls,
+    // TLS from the start, usually on port 465
+    Tls,
+    None,
+}
+
+#[derive(Deserialize, Serialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
     pub log_level: String,
 
@@ -19,15 +93,66 @@ pub struct Config {
     pub hash: String,
     pub secret: String,
     pub expiry: u64,
+    pub session_lifetime: u64,
+    pub users: Vec<User>,
+
+    pub login_backoff_after: u32,
+    pub login_backoff_max: u64,
+    pub login_lockout_after: u32,
+    pub login_lockout_time: u64,
+    pub login_global_limit: u32,
 
     #[cfg(feature = "frontend")]
     pub nodes: Vec<String>,
 
     pub terminal_user: String,
+    pub terminal_command: Vec<String>,
+    pub terminal_term: String,
+    pub terminal_env: HashMap<String, String>,
+    pub terminal_dir: String,
+    pub terminal_uid: Option<u32>,
+    pub terminal_gid: Option<u32>,
+    pub terminal_idle_timeout: u64,
+    pub terminal_scrollback: usize,
+    pub terminal_recording: bool,
+    pub terminal_recording_days: u64,
+    pub terminal_share_expiry: u64,
 
     pub update_check: bool,
 
     pub temp_unit: TempUnit,
+
+    pub history: bool,
+    pub data_dir: String,
+
+    pub metrics: bool,
+    pub metrics_token: String,
+
+    pub statistics_interval: u64,
+    pub process_interval: u64,
+    pub interval_min: u64,
+    pub interval_max: u64,
+    pub background_interval: u64,
+
+    pub audit_max_size: u64,
+    pub audit_files: u32,
+
+    pub disk_exclude_types: Vec<String>,
+    pub disk_include: Vec<String>,
+    pub disk_exclude: Vec<String>,
+
+    pub network_total_include: Vec<String>,
+
+    pub alerts: Vec<AlertRule>,
+    pub alert_service_interval: u64,
+    pub alert_webhook: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_security: SmtpSecurity,
+    pub smtp_user: String,
+    pub smtp_pass: String,
+    pub smtp_from: String,
+    pub smtp_to: Vec<String>,
 }
 
 impl Default for Config {
@@ -45,15 +170,92 @@ impl Default for Config {
             hash: String::new(),
             secret: String::new(),
             expiry: 3600,
+            session_lifetime: 86400,
+            users: Vec::new(),
+
+            login_backoff_after: 3,
+            login_backoff_max: 60,
+            login_lockout_after: 10,
+            login_lockout_time: 900,
+            login_global_limit: 30,
 
             #[cfg(feature = "frontend")]
             nodes: Vec::new(),
 
             terminal_user: "root".to_string(),
+            terminal_command: Vec::new(),
+            terminal_term: "xterm".to_string(),
+            terminal_env: HashMap::new(),
+            terminal_dir: String::new(),
+            terminal_uid: None,
+            terminal_gid: None,
+            terminal_idle_timeout: 300,
+            terminal_scrollback: 65536,
+            terminal_recording: false,
+            terminal_recording_days: 30,
+            terminal_share_expiry: 600,
 
             update_check: true,
 
             temp_unit: TempUnit::Celsius,
+
+            history: true,
+            data_dir: String::new(),
+
+            metrics: false,
+            metrics_token: String::new(),
+
+            statistics_interval: 1000,
+            process_interval: 1000,
+            interval_min: 250,
+            interval_max: 60000,
+            background_interval: 5000,
+
+            audit_max_size: 10,
+            audit_files: 5,
+
+            disk_exclude_types: [
+                "autofs",
+                "binfmt_misc",
+                "bpf",
+                "cgroup",
+                "cgroup2",
+                "configfs",
+                "debugfs",
+                "devpts",
+                "devtmpfs",
+                "efivarfs",
+                "fusectl",
+                "hugetlbfs",
+                "mqueue",
+                "nsfs",
+                "proc",
+                "pstore",
+                "ramfs",
+                "rpc_pipefs",
+                "securityfs",
+                "squashfs",
+                "sysfs",
+                "tmpfs",
+                "tracefs",
+            ]
+            .map(String::from)
+            .to_vec(),
+            disk_include: Vec::new(),
+            disk_exclude: Vec::new(),
+
+            network_total_include: Vec::new(),
+
+            alerts: Vec::new(),
+            alert_service_interval: 60,
+            alert_webhook: String::new(),
+            smtp_host: String::new(),
+            smtp_port: 587,
+            smtp_security: SmtpSecurity::Starttls,
+            smtp_user: String::new(),
+            smtp_pass: String::new(),
+            smtp_from: String::new(),
+            smtp_to: Vec::new(),
         }
     }
 }
@@ -63,7 +265,36 @@ pub fn config() -> Config {
     cfgpath.set_file_name("config.toml");
     Figment::from(Serialized::defaults(Config::default()))
         .merge(Toml::file(cfgpath))
-        .merge(Env::prefixed("DP_DASHBOARD_").ignore(&["hash", "secret"]))
+        .merge(Env::prefixed("DP_DASHBOARD_").ignore(&["hash", "secret", "users", "metrics_token"]))
         .extract()
         .expect("Error reading config")
 }
+
+// Files created by the dashboard are stored next to the binary, unless another directory is given
+pub fn data_path(file: &str) -> std::path::PathBuf {
+    let dir = &crate::shared::CONFIG.data_dir;
+    if dir.is_empty() {
+        let mut path = std::env::current_exe().expect("Couldn't get data path");
+        path.set_file_name(file);
+        path
+    } else {
+        std::path::Path::new(dir).join(file)
+    }
+}
+
+// For files holding secrets, so they're only readable by the dashboard's user
+// Written to a temporary file first, so a crash can't leave half of one behind
+pub fn write_private(file: &str, data: &[u8]) -> anyhow::Result<()> {
+    let path = data_path(file);
+    let tmp_path = path.with_extension("tmp");
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(data))
+        .with_context(|| format!("Couldn't write {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, &path)
+        .with_context(|| format!("Couldn't replace {}", path.display()))
+}
@@ -0,0 +1,173 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{LazyLock, Mutex, MutexGuard, PoisonError};
+use tracing::instrument;
+
+use crate::{handle_error, samplers, shared};
+
+// Step (in seconds) and number of points for each tier, which gives 1 hour of 1s data, 1 day of 1m data and 1 week of 1h data
+// The first tier gets a point every background_interval, so it covers more than an hour when that's longer
+const TIERS: [(u64, usize); 3] = [(1, 3600), (60, 1440), (3600, 168)];
+
+// How often to write the history to disk, in seconds, besides when shutting down
+// Writing rarely spares SD cards, at the cost of losing up to an hour of history on a crash or power loss
+const SAVE_INTERVAL: u64 = 3600;
+
+static HISTORY: LazyLock<Mutex<History>> = LazyLock::new(Mutex::default);
+
+#[derive(Serialize, Deserialize, Default)]
+struct Tier {
+    points: VecDeque<shared::HistoryPoint>,
+    // Points from the tier below that haven't been combined into a point for this tier yet
+    pending: Vec<shared::HistoryPoint>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct History {
+    tiers: [Tier; 3],
+}
+
+fn history() -> MutexGuard<'static, History> {
+    // The history is always left in a valid state, so a poisoned lock is fine to use
+    HISTORY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn average(points: &[shared::HistoryPoint], time: u64) -> shared::HistoryPoint {
+    let len = points.len() as u64;
+    let temps = points.iter().filter_map(|x| x.temp).collect::<Vec<_>>();
+    shared::HistoryPoint {
+        time,
+        cpu: points.iter().map(|x| x.cpu).sum::<f32>() / len as f32,
+        ram: points.iter().map(|x| x.ram).sum::<u64>() / len,
+        swap: points.iter().map(|x| x.swap).sum::<u64>() / len,
+        disk: points.iter().map(|x| x.disk).sum::<u64>() / len,
+        sent: points.iter().map(|x| x.sent).sum::<u64>() / len,
+        received: points.iter().map(|x| x.received).sum::<u64>() / len,
+        temp: (!temps.is_empty()).then(|| {
+            (temps.iter().copied().map(i32::from).sum::<i32>() / temps.len() as i32) as i16
+        }),
+    }
+}
+
+impl History {
+    fn push(&mut self, index: usize, point: shared::HistoryPoint) {
+        let tier = &mut self.tiers[index];
+        if tier.points.len() >= TIERS[index].1 {
+            tier.points.pop_front();
+        }
+        tier.points.push_back(point);
+
+        let Some(&(step, _)) = TIERS.get(index + 1) else {
+            return;
+        };
+        let next = &mut self.tiers[index + 1];
+        // Once a point lands in a new bucket, the old bucket is complete and can be moved up a tier
+        let bucket = next.pending.first().map(|x| x.time / step);
+        if let Some(bucket) = bucket.filter(|&x| x != point.time / step) {
+            let combined = average(&next.pending, bucket * step);
+            next.pending.clear();
+            next.pending.push(point);
+            self.push(index + 1, combined);
+        } else {
+            next.pending.push(point);
+        }
+    }
+
+    fn query(&self, range: u64) -> Vec<shared::HistoryPoint> {
+        // Use the most detailed tier that still covers the whole range
+        let index = TIERS
+            .iter()
+            .position(|&(step, len)| step * len as u64 >= range)
+            .unwrap_or(TIERS.len() - 1);
+        let start = jsonwebtoken::get_current_timestamp().saturating_sub(range);
+        self.tiers[index]
+            .points
+            .iter()
+            .filter(|x| x.time >= start)
+            .copied()
+            .collect()
+    }
+}
+
+pub fn query(range: u64) -> shared::HistoryData {
+    shared::HistoryData {
+        range,
+        points: history().query(range),
+    }
+}
+
+fn point(data: &shared::SysData) -> shared::HistoryPoint {
+    shared::HistoryPoint {
+        time: jsonwebtoken::get_current_timestamp(),
+        cpu: data.cpu,
+        ram: data.ram.used,
+        swap: data.swap.used,
+        disk: data.disk.used,
+        sent: data.network.sent,
+        received: data.network.received,
+        temp: data.temp.temp,
+    }
+}
+
+async fn load(path: &std::path::Path) -> anyhow::Result<()> {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            tracing::debug!(
+                "No statistics history at {}, starting fresh",
+                path.display()
+            );
+            return Ok(());
+        }
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Couldn't read statistics history {}", path.display()))
+        }
+    };
+    *history() = serde_json::from_slice(&data)
+        .with_context(|| format!("Couldn't parse statistics history {}", path.display()))?;
+    Ok(())
+}
+
+fn path() -> std::path::PathBuf {
+    crate::config::data_path("history.json")
+}
+
+pub async fn save() -> anyhow::Result<()> {
+    let path = path();
+    let data = serde_json::to_vec(&*history()).context("Couldn't serialize statistics history")?;
+    // Write to a temporary file first, so a crash can't leave behind a half-written history
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, data)
+        .await
+        .with_context(|| format!("Couldn't write statistics history {}", tmp_path.display()))?;
+    tokio::fs::rename(&tmp_path, &path)
+        .await
+        .with_context(|| format!("Couldn't save statistics history {}", path.display()))
+}
+
+// Runs for the whole lifetime of the dashboard, whether or not anyone is connected
+// That keeps the statistics sampler running too, at least every background_interval
+#[instrument(skip_all)]
+pub async fn collector() {
+    handle_error!(load(&path()).await);
+
+    let mut statistics = samplers::background();
+    let mut last_save = jsonwebtoken::get_current_timestamp();
+
+    while let Some(data) = statistics.next().await {
+        let point = point(&data);
+        history().push(0, point);
+        if point.time >= last_save + SAVE_INTERVAL {
+            last_save = point.time;
+            handle_error!(save().await);
+        }
+    }
+}
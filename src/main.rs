@@ -10,15 +10,35 @@ use anyhow::Context;
 use hyper::service::{make_service_fn, service_fn};
 use std::{net::IpAddr, str::FromStr};
 
+mod alerts;
+mod api;
+mod apitokens;
+mod audit;
+mod auth;
+mod cli;
 mod config;
+mod history;
+mod metrics;
+mod notifiers;
 mod page_handlers;
+mod ratelimit;
+mod recordings;
 mod routes;
+mod samplers;
+mod sessions;
 mod shared;
 mod socket_handlers;
 mod systemdata;
+mod terminals;
+mod totp;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Some((cmd, args)) = args.split_first() {
+        return cli::run(cmd, args);
+    }
+
     tracing::subscriber::set_global_default(
         tracing_subscriber::FmtSubscriber::builder()
             .with_max_level(
@@ -30,6 +50,16 @@ async fn main() -> anyhow::Result<()> {
     )
     .context("Couldn't init logger")?;
 
+    if CONFIG.history {
+        tokio::spawn(history::collector());
+    }
+    if !CONFIG.alerts.is_empty() {
+        tokio::spawn(alerts::rules());
+    }
+    if CONFIG.alert_service_interval != 0 {
+        tokio::spawn(alerts::services());
+    }
+
     let addr = std::net::SocketAddr::from((IpAddr::from([0; 8]), CONFIG.port));
 
     let tcp = tokio::net::TcpListener::bind(&addr)
@@ -50,7 +80,7 @@ async fn main() -> anyhow::Result<()> {
                             .map_or("unknown", |x| x.to_str().unwrap_or("unknown"))
                     );
                 });
-                routes::router(req, span).await
+                routes::router(req, span, remote_addr).await
             }))
         }
     });
@@ -74,11 +104,34 @@ async fn main() -> anyhow::Result<()> {
 
     let mut server = hyper::server::Server::builder(acceptor).serve(make_svc);
 
-    // Ignore result, because it will never be an error
-    loop {
-        let _res = (&mut server).await.context("Server error").or_else(|e| {
-            tracing::warn!("{:?}", e);
-            anyhow::Ok(())
-        });
+    tokio::select! {
+        // Ignore result, because it will never be an error
+        () = async {
+            loop {
+                let _res = (&mut server).await.context("Server error").or_else(|e| {
+                    tracing::warn!("{:?}", e);
+                    anyhow::Ok(())
+                });
+            }
+        } => {}
+        () = shutdown() => tracing::info!("Shutting down"),
+    }
+
+    // Otherwise only written to disk rarely
+    if CONFIG.history {
+        history::save().await?;
+    }
+    Ok(())
+}
+
+async fn shutdown() {
+    let mut terminate = handle_error!(
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .context("Couldn't listen for SIGTERM"),
+        return std::future::pending().await
+    );
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
     }
 }
@@ -0,0 +1,162 @@
+use anyhow::Context;
+use psutil::{network, process};
+use std::fmt::{Display, Write};
+use tracing::instrument;
+
+use crate::{handle_error, samplers, shared, systemdata};
+
+const SERVICE_STATES: [&str; 4] = ["active", "inactive", "failed", "unknown"];
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Writing to a String can't fail, so the results of write! are ignored
+struct Metrics(String);
+
+impl Metrics {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# TYPE {name} {kind}");
+        let _ = writeln!(self.0, "# HELP {name} {help}");
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(key, value)| format!("{key}=\"{}\"", escape_label(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = write!(self.0, "{{{labels}}}");
+        }
+        let _ = writeln!(self.0, " {value}");
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, value: impl Display) {
+        self.family(name, "gauge", help);
+        self.sample(name, &[], value);
+    }
+}
+
+// Usage comes from the same samples as the statistics page
+fn render_sample(metrics: &mut Metrics, data: &shared::SysData) {
+    metrics.gauge(
+        "dietpi_cpu_usage_percent",
+        "CPU usage over the last sampling interval.",
+        data.cpu,
+    );
+
+    for (name, description, data) in [
+        ("memory", "RAM", &data.ram),
+        ("swap", "swap", &data.swap),
+        ("disk", "root filesystem", &data.disk),
+    ] {
+        metrics.gauge(
+            &format!("dietpi_{name}_used_bytes"),
+            &format!("Used {description} in bytes."),
+            data.used,
+        );
+        metrics.gauge(
+            &format!("dietpi_{name}_total_bytes"),
+            &format!("Total {description} in bytes."),
+            data.total,
+        );
+    }
+
+    if let Some(temp) = data.temp.temp {
+        metrics.gauge(
+            "dietpi_cpu_temperature_celsius",
+            "CPU temperature in degrees Celsius.",
+            temp,
+        );
+    }
+}
+
+// Counters since boot, which are read when scraped
+fn render_counters(metrics: &mut Metrics) {
+    let mut nics = handle_error!(
+        network::NetIoCountersCollector::default()
+            .net_io_counters_pernic()
+            .context("Couldn't get network data")
+            .map(|x| x.into_iter().collect::<Vec<_>>()),
+        Vec::new()
+    );
+    nics.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    metrics.family(
+        "dietpi_network_received_bytes",
+        "counter",
+        "Bytes received per network interface.",
+    );
+    for (nic, counters) in &nics {
+        metrics.sample(
+            "dietpi_network_received_bytes_total",
+            &[("interface", nic)],
+            counters.bytes_recv(),
+        );
+    }
+    metrics.family(
+        "dietpi_network_sent_bytes",
+        "counter",
+        "Bytes sent per network interface.",
+    );
+    for (nic, counters) in &nics {
+        metrics.sample(
+            "dietpi_network_sent_bytes_total",
+            &[("interface", nic)],
+            counters.bytes_sent(),
+        );
+    }
+
+    let pids = handle_error!(
+        process::pids().context("Couldn't get list of processes"),
+        Vec::new()
+    );
+    metrics.gauge(
+        "dietpi_processes",
+        "Number of running processes.",
+        pids.len(),
+    );
+}
+
+// Renders everything in the OpenMetrics text format, skipping any data that couldn't be gathered
+#[instrument(skip_all)]
+pub async fn render() -> String {
+    let mut metrics = Metrics(String::new());
+
+    if let Some(data) = samplers::statistics().next().await {
+        render_sample(&mut metrics, &data);
+    }
+    render_counters(&mut metrics);
+
+    if let Some(services) = handle_error!(systemdata::services().await.map(Some), None) {
+        metrics.family(
+            "dietpi_service_state",
+            "stateset",
+            "State of each service managed by DietPi-Services.",
+        );
+        for service in &services {
+            for state in SERVICE_STATES {
+                metrics.sample(
+                    "dietpi_service_state",
+                    &[("service", &service.name), ("dietpi_service_state", state)],
+                    u8::from(service.status == state),
+                );
+            }
+        }
+    }
+
+    if let Some(upgrades) = handle_error!(systemdata::apt_upgrades().await.map(Some), None) {
+        metrics.gauge(
+            "dietpi_apt_upgrades",
+            "Number of upgradable APT packages.",
+            upgrades,
+        );
+    }
+
+    metrics.0.push_str("# EOF\n");
+    metrics.0
+}
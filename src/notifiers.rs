@@ -0,0 +1,314 @@
+use anyhow::Context;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+
+use crate::config::SmtpSecurity;
+use crate::handle_error;
+use crate::shared::{self, CONFIG};
+
+// Longest that one delivery can take, so a server that doesn't answer doesn't keep tasks around
+const TIMEOUT: Duration = Duration::from_secs(30);
+// Where Debian keeps the system's CA certificates
+const CA_FILE: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    host: &'a str,
+    #[serde(flatten)]
+    alert: &'a shared::Alert,
+}
+
+// Loaded again for every alert, which is rare enough that it doesn't matter
+fn tls_connector() -> anyhow::Result<tokio_rustls::TlsConnector> {
+    let file = std::fs::File::open(CA_FILE)
+        .with_context(|| format!("Couldn't open CA certificates {CA_FILE}"))?;
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_parsable_certificates(
+        rustls_pemfile::certs(&mut std::io::BufReader::new(file)).flatten(),
+    );
+    anyhow::ensure!(!roots.is_empty(), "No CA certificates in {CA_FILE}");
+    Ok(tokio_rustls::TlsConnector::from(Arc::new(
+        rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    )))
+}
+
+async fn tls_connect(
+    stream: TcpStream,
+    host: &str,
+) -> anyhow::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let name = rustls::pki_types::ServerName::try_from(host.to_string())
+        .with_context(|| format!("Invalid server name {host}"))?;
+    tls_connector()?
+        .connect(name, stream)
+        .await
+        .with_context(|| format!("Couldn't start TLS with {host}"))
+}
+
+async fn send_request<S>(
+    stream: S,
+    req: hyper::Request<hyper::Body>,
+) -> anyhow::Result<hyper::StatusCode>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::handshake(stream)
+        .await
+        .context("Couldn't connect to webhook")?;
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            tracing::debug!("Webhook connection failed: {}", err);
+        }
+    });
+    Ok(sender
+        .send_request(req)
+        .await
+        .context("Couldn't send webhook request")?
+        .status())
+}
+
+#[tracing::instrument(skip_all)]
+async fn webhook(payload: &Payload<'_>) -> anyhow::Result<()> {
+    let uri = CONFIG
+        .alert_webhook
+        .parse::<hyper::Uri>()
+        .context("Invalid webhook URL")?;
+    let https = match uri.scheme_str() {
+        Some("https") => true,
+        Some("http") => false,
+        _ => anyhow::bail!("Webhook URL has to start with http:// or https://"),
+    };
+    let authority = uri.authority().context("Webhook URL has no host")?;
+    // IPv6 addresses are in brackets
+    let host = authority
+        .host()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = authority.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let req = hyper::Request::post(uri.path_and_query().map_or("/", |x| x.as_str()))
+        .header(hyper::header::HOST, authority.as_str())
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(hyper::Body::from(
+            serde_json::to_vec(payload).context("Couldn't serialize alert")?,
+        ))
+        .context("Couldn't build webhook request")?;
+    let stream = TcpStream::connect((host, port))
+        .await
+        .with_context(|| format!("Couldn't connect to webhook host {host}"))?;
+    let status = if https {
+        send_request(tls_connect(stream, host).await?, req).await?
+    } else {
+        send_request(stream, req).await?
+    };
+    anyhow::ensure!(status.is_success(), "Webhook returned {status}");
+    Ok(())
+}
+
+// Reads a (possibly multiline) reply, and checks that it's the expected kind (like 2 for success)
+async fn smtp_reply<S: AsyncRead + Unpin>(
+    stream: &mut BufReader<S>,
+    expected: u8,
+) -> anyhow::Result<()> {
+    loop {
+        let mut line = String::new();
+        let read = stream
+            .read_line(&mut line)
+            .await
+            .context("Couldn't read from mail server")?;
+        anyhow::ensure!(read != 0, "Mail server closed the connection");
+        // Lines like "250-SIZE" are followed by more, the last one is like "250 HELP"
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        anyhow::ensure!(
+            line.as_bytes().first() == Some(&(b'0' + expected)),
+            "Mail server replied {}",
+            line.trim_end()
+        );
+        return Ok(());
+    }
+}
+
+async fn smtp_command<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    command: &str,
+    expected: u8,
+) -> anyhow::Result<()> {
+    stream
+        .get_mut()
+        .write_all(format!("{command}\r\n").as_bytes())
+        .await
+        .context("Couldn't write to mail server")?;
+    smtp_reply(stream, expected).await
+}
+
+// Like "Sun, 18 Oct 2026 08:25:26 +0000", which is what the Date header needs
+#[allow(clippy::cast_possible_truncation)]
+fn email_date(timestamp: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = timestamp / 86400;
+    let secs = timestamp % 86400;
+    // Days since 1970 to a date, using Howard Hinnant's civil_from_days
+    let era = (days + 719_468) / 146_097;
+    let day_of_era = (days + 719_468) % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Counted from March, so leap days are at the end
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{}, {day} {} {year} {:02}:{:02}:{:02} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        MONTHS[(month - 1) as usize],
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// Header values have to be ASCII, anything else is sent as RFC 2047 encoded words
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    let mut words = Vec::new();
+    let mut start = 0;
+    while start < value.len() {
+        // Encoded words can be at most 75 characters, which 45 bytes of text stays under
+        let mut end = (start + 45).min(value.len());
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        words.push(format!(
+            "=?utf-8?B?{}?=",
+            data_encoding::BASE64.encode(&value.as_bytes()[start..end])
+        ));
+        start = end;
+    }
+    words.join("\r\n ")
+}
+
+// Everything after the greeting (and STARTTLS)
+async fn smtp_session<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: BufReader<S>,
+    payload: &Payload<'_>,
+) -> anyhow::Result<()> {
+    smtp_command(&mut stream, &format!("EHLO {}", payload.host), 2).await?;
+    if !CONFIG.smtp_user.is_empty() {
+        let login = data_encoding::BASE64
+            .encode(format!("\0{}\0{}", CONFIG.smtp_user, CONFIG.smtp_pass).as_bytes());
+        smtp_command(&mut stream, &format!("AUTH PLAIN {login}"), 2)
+            .await
+            .context("Couldn't log in to mail server")?;
+    }
+    smtp_command(&mut stream, &format!("MAIL FROM:<{}>", CONFIG.smtp_from), 2).await?;
+    for to in &CONFIG.smtp_to {
+        smtp_command(&mut stream, &format!("RCPT TO:<{to}>"), 2).await?;
+    }
+    smtp_command(&mut stream, "DATA", 3).await?;
+
+    let state = if payload.alert.firing {
+        "Alert"
+    } else {
+        "Resolved"
+    };
+    let mut id = [0u8; 16];
+    getrandom::getrandom(&mut id).context("Couldn't generate random message ID")?;
+    let domain = CONFIG
+        .smtp_from
+        .rsplit_once('@')
+        .map_or(payload.host, |(_, domain)| domain);
+    let mut message = format!(
+        "Date: {}\r\nMessage-ID: <{}@{domain}>\r\nFrom: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        email_date(jsonwebtoken::get_current_timestamp()),
+        hex::encode(id),
+        CONFIG.smtp_from,
+        CONFIG.smtp_to.join(", "),
+        encode_header(&format!("[{}] {state}: {}", payload.host, payload.alert.title)),
+    );
+    for line in payload.alert.message.lines() {
+        // Lines starting with a dot get another one, so they can't end the message
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push('.');
+    smtp_command(&mut stream, &message, 2).await?;
+    // The mail is already sent, so there's no need to wait for the reply
+    let _quit = stream.get_mut().write_all(b"QUIT\r\n").await;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn email(payload: &Payload<'_>) -> anyhow::Result<()> {
+    let server = CONFIG.smtp_host.as_str();
+    let stream = TcpStream::connect((server, CONFIG.smtp_port))
+        .await
+        .with_context(|| format!("Couldn't connect to mail server {server}"))?;
+    match CONFIG.smtp_security {
+        SmtpSecurity::Tls => {
+            let mut stream = BufReader::new(tls_connect(stream, server).await?);
+            smtp_reply(&mut stream, 2).await?;
+            smtp_session(stream, payload).await
+        }
+        SmtpSecurity::Starttls => {
+            let mut stream = BufReader::new(stream);
+            smtp_reply(&mut stream, 2).await?;
+            smtp_command(&mut stream, &format!("EHLO {}", payload.host), 2).await?;
+            smtp_command(&mut stream, "STARTTLS", 2).await?;
+            let stream = tls_connect(stream.into_inner(), server).await?;
+            smtp_session(BufReader::new(stream), payload).await
+        }
+        SmtpSecurity::None => {
+            let mut stream = BufReader::new(stream);
+            smtp_reply(&mut stream, 2).await?;
+            smtp_session(stream, payload).await
+        }
+    }
+}
+
+// Sends the alert to the webhook and by email, if those are set up, without waiting for it
+pub fn deliver(alert: shared::Alert) {
+    let webhook_enabled = !CONFIG.alert_webhook.is_empty();
+    let email_enabled = !CONFIG.smtp_host.is_empty() && !CONFIG.smtp_to.is_empty();
+    if !webhook_enabled && !email_enabled {
+        return;
+    }
+    tokio::spawn(async move {
+        let host = psutil::host::info().hostname().to_string();
+        let payload = Payload {
+            host: &host,
+            alert: &alert,
+        };
+        if webhook_enabled {
+            handle_error!(tokio::time::timeout(TIMEOUT, webhook(&payload))
+                .await
+                .context("Webhook timed out")
+                .and_then(|x| x));
+        }
+        if email_enabled {
+            handle_error!(tokio::time::timeout(TIMEOUT, email(&payload))
+                .await
+                .context("Sending email timed out")
+                .and_then(|x| x));
+        }
+    });
+}
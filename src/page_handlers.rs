@@ -1,66 +1,74 @@
 use anyhow::Context;
-use std::time::Duration;
 use tokio::process::Command;
-use tokio::sync::mpsc::Receiver;
-use tokio::time::sleep;
+use tokio::sync::{mpsc::Receiver, watch};
 use tracing::instrument;
 
 use crate::{
-    handle_error,
+    apitokens, audit, handle_error, recordings, samplers, sessions,
     shared::{self, RequestTypes, SocketSend},
     systemdata,
 };
 
 type RecvChannel = Receiver<Option<shared::RequestTypes>>;
+type ActorRecv = watch::Receiver<audit::Actor>;
+
+// Records with whoever is logged in on the socket right now
+fn record<T>(
+    actor: &ActorRecv,
+    action: &str,
+    args: &[impl AsRef<str>],
+    result: anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    audit::record(&actor.borrow(), action, args, result)
+}
 
 #[instrument(level = "debug", skip_all)]
-fn main_handler_getter(
-    cpu_collector: &mut psutil::cpu::CpuPercentCollector,
+pub fn main_handler_getter(
+    cpu_collector: &mut systemdata::CpuCollector,
     net_collector: &mut psutil::network::NetIoCountersCollector,
-    prev_data: &mut shared::NetData,
+    prev_data: &mut systemdata::NetPrev,
+    prev_disk_io: &mut systemdata::DiskIoPrev,
 ) -> anyhow::Result<shared::SysData> {
+    let (cpu, cpu_detail) = systemdata::cpu_detail(cpu_collector)?;
+    let (network, nics) = systemdata::network(net_collector, prev_data)?;
     Ok(shared::SysData {
-        cpu: systemdata::cpu(cpu_collector)?,
+        cpu,
+        cpu_detail,
         ram: systemdata::ram()?,
         swap: systemdata::swap()?,
+        memory: systemdata::memory_detail()?,
+        pressure: systemdata::pressure(),
         disk: systemdata::disk()?,
-        network: systemdata::network(net_collector, prev_data)?,
+        filesystems: systemdata::filesystems()?,
+        disk_io: systemdata::disk_io(prev_disk_io)?,
+        network,
+        nics,
         temp: systemdata::temp(),
+        sensors: systemdata::sensors(),
     })
 }
 
 // Return true if error was related to websocket, false otherwise
 #[instrument(skip_all)]
 pub async fn main_handler(socket_send: &mut SocketSend, data_recv: &mut RecvChannel) -> bool {
-    let mut cpu_collector = handle_error!(
-        psutil::cpu::CpuPercentCollector::new().context("Couldn't init cpu collector"),
-        return false
-    );
-
-    let mut net_collector = psutil::network::NetIoCountersCollector::default();
-    let mut prev_data = net_collector.net_io_counters().map_or_else(
-        |_err| {
-            tracing::debug!("Couldn't get original network counter data, starting with u64::MAX");
-            shared::NetData {
-                received: u64::MAX,
-                sent: u64::MAX,
-            }
-        },
-        |counters| shared::NetData {
-            received: counters.bytes_recv(),
-            sent: counters.bytes_sent(),
-        },
-    );
+    let mut statistics = samplers::statistics();
 
     loop {
         tokio::select! {
             biased;
-            data = data_recv.recv() => if let Some(Some(_)) = data {} else { return false },
-            res = socket_send
-            .send(shared::BackendData::Statistic(handle_error!(main_handler_getter(&mut cpu_collector, &mut net_collector, &mut prev_data), shared::SysData::default())))
-            => {
-                sleep(Duration::from_secs(1)).await;
-                if res.is_err() {
+            data = data_recv.recv() => match data {
+                Some(Some(RequestTypes::Interval { interval })) => statistics.set_interval(interval),
+                Some(Some(RequestTypes::History { range })) => {
+                    if socket_send.send(shared::BackendData::History(crate::history::query(range))).await.is_err() {
+                        tracing::debug!("Socket send failed, returning");
+                        return true;
+                    }
+                }
+                Some(Some(_)) => {}
+                _ => return false,
+            },
+            Some(data) = statistics.next() => {
+                if socket_send.send(shared::BackendData::Statistic(Box::new(data))).await.is_err() {
                     tracing::debug!("Socket send failed, returning");
                     return true;
                 }
@@ -70,7 +78,7 @@ pub async fn main_handler(socket_send: &mut SocketSend, data_recv: &mut RecvChan
 }
 
 #[instrument(level = "debug", skip_all)]
-fn process_handler_helper(cmd: &str, arg: Option<&str>) -> anyhow::Result<()> {
+pub fn process_handler_helper(cmd: &str, arg: Option<&str>) -> anyhow::Result<()> {
     if let Some(arg) = arg {
         let process = psutil::process::Process::new(
             arg.parse::<u32>()
@@ -93,26 +101,31 @@ fn process_handler_helper(cmd: &str, arg: Option<&str>) -> anyhow::Result<()> {
 }
 
 #[instrument(skip_all)]
-pub async fn process_handler(socket_send: &mut SocketSend, data_recv: &mut RecvChannel) -> bool {
+pub async fn process_handler(
+    socket_send: &mut SocketSend,
+    data_recv: &mut RecvChannel,
+    actor: &ActorRecv,
+) -> bool {
+    let mut processes = samplers::processes();
+
     loop {
         tokio::select! {
             biased;
             data = data_recv.recv() => match data {
-                Some(Some(RequestTypes::Cmd { cmd, args: Some(args) })) => handle_error!(process_handler_helper(&cmd, args.first().map(String::as_str))),
+                Some(Some(RequestTypes::Cmd { cmd, args: Some(args) })) => {
+                    let result = process_handler_helper(&cmd, args.first().map(String::as_str));
+                    handle_error!(record(actor, &format!("process.{cmd}"), &args, result));
+                }
+                Some(Some(RequestTypes::Interval { interval })) => processes.set_interval(interval),
                 Some(Some(_)) => {}
                 _ => return false,
             },
-            res = socket_send
-                .send(shared::BackendData::Process(shared::ProcessList {
-                        processes: handle_error!(systemdata::processes().await, Vec::new()),
-                    }
-                )) => {
-                    sleep(Duration::from_secs(1)).await;
-                    if res.is_err() {
-                        tracing::debug!("Socket send failed, returning");
-                        return true;
-                    }
-                },
+            Some(data) = processes.next() => {
+                if socket_send.send(shared::BackendData::Process(shared::ProcessList { processes: data })).await.is_err() {
+                    tracing::debug!("Socket send failed, returning");
+                    return true;
+                }
+            },
         }
     }
 }
@@ -152,7 +165,11 @@ pub async fn software_handler_helper(
 }
 
 #[instrument(skip_all)]
-pub async fn software_handler(socket_send: &mut SocketSend, data_recv: &mut RecvChannel) -> bool {
+pub async fn software_handler(
+    socket_send: &mut SocketSend,
+    data_recv: &mut RecvChannel,
+    actor: &ActorRecv,
+) -> bool {
     let software = handle_error!(systemdata::dpsoftware().await, (Vec::new(), Vec::new()));
     if socket_send
         .send(shared::BackendData::Software(shared::DPSoftwareList {
@@ -172,9 +189,11 @@ pub async fn software_handler(socket_send: &mut SocketSend, data_recv: &mut Recv
             args: Some(args),
         } = data
         {
+            let result = software_handler_helper(&cmd, &args).await;
+            let result = record(actor, &format!("software.{cmd}"), &args, result);
             if socket_send
                 .send(shared::BackendData::Software(handle_error!(
-                    software_handler_helper(&cmd, &args).await,
+                    result,
                     shared::DPSoftwareList::default()
                 )))
                 .await
@@ -189,7 +208,11 @@ pub async fn software_handler(socket_send: &mut SocketSend, data_recv: &mut Recv
 }
 
 #[instrument(skip_all)]
-pub async fn management_handler(socket_send: &mut SocketSend, data_recv: &mut RecvChannel) -> bool {
+pub async fn management_handler(
+    socket_send: &mut SocketSend,
+    data_recv: &mut RecvChannel,
+    actor: &ActorRecv,
+) -> bool {
     if socket_send
         .send(shared::BackendData::Management(handle_error!(
             systemdata::host().await,
@@ -205,17 +228,37 @@ pub async fn management_handler(socket_send: &mut SocketSend, data_recv: &mut Re
         if let RequestTypes::Cmd { cmd, args: _ } = data {
             tracing::info!("Running command {}", &cmd);
             // Don't care about the Ok value, so remove it to make the type checker happy
-            handle_error!(Command::new(&cmd)
+            let result = Command::new(&cmd)
                 .spawn()
                 .map(|_| ())
-                .with_context(|| format!("Couldn't spawn command {}", &cmd)));
+                .with_context(|| format!("Couldn't spawn command {}", &cmd));
+            handle_error!(record(actor, "management.run", &[&cmd], result));
         }
     }
     false
 }
 
+#[instrument(level = "debug", skip_all)]
+pub async fn service_handler_helper(cmd: &str, name: &str) -> anyhow::Result<()> {
+    tracing::info!("Running {} on service {}", cmd, name);
+    let status = Command::new("systemctl")
+        .args([cmd, name])
+        .status()
+        .await
+        .with_context(|| format!("Couldn't {cmd} service {name}"))?;
+    anyhow::ensure!(
+        status.success(),
+        "Couldn't {cmd} service {name}, systemctl exited with {status}"
+    );
+    Ok(())
+}
+
 #[instrument(skip_all)]
-pub async fn service_handler(socket_send: &mut SocketSend, data_recv: &mut RecvChannel) -> bool {
+pub async fn service_handler(
+    socket_send: &mut SocketSend,
+    data_recv: &mut RecvChannel,
+    actor: &ActorRecv,
+) -> bool {
     if socket_send
         .send(shared::BackendData::Service(shared::ServiceList {
             services: handle_error!(systemdata::services().await, Vec::new()),
@@ -233,14 +276,64 @@ pub async fn service_handler(socket_send: &mut SocketSend, data_recv: &mut RecvC
         } = data
         {
             if let Some(arg) = args.first() {
-                handle_error!(Command::new("systemctl")
-                    .args([&cmd, arg])
-                    .spawn()
-                    .map(|_| ()) // Don't care about the Ok value, so remove it to make the type checker happy
-                    .with_context(|| format!("Couldn't {} service {arg}", &cmd)));
+                // Restarting a service can take a while, so don't hold up the page until it's done
+                let actor = actor.borrow().clone();
+                let arg = arg.clone();
+                let socket_send = socket_send.clone();
+                tokio::spawn(async move {
+                    let result = service_handler_helper(&cmd, &arg).await;
+                    handle_error!(audit::record(
+                        &actor,
+                        &format!("service.{cmd}"),
+                        &[&arg],
+                        result
+                    ));
+                    // The list only changes once the action has finished
+                    // A closed socket is noticed by the page's own loop
+                    let _send = socket_send
+                        .send(shared::BackendData::Service(shared::ServiceList {
+                            services: handle_error!(systemdata::services().await, Vec::new()),
+                        }))
+                        .await;
+                });
+            }
+        }
+    }
+    false
+}
+
+#[instrument(skip_all)]
+pub async fn sessions_handler(
+    socket_send: &mut SocketSend,
+    data_recv: &mut RecvChannel,
+    actor: &ActorRecv,
+) -> bool {
+    if socket_send
+        .send(shared::BackendData::Sessions(shared::SessionList {
+            sessions: sessions::list(),
+        }))
+        .await
+        .is_err()
+    {
+        tracing::debug!("Socket send failed, returning");
+        return true;
+    }
+    while let Some(Some(data)) = data_recv.recv().await {
+        if let RequestTypes::Cmd {
+            cmd,
+            args: Some(args),
+        } = data
+        {
+            if let (Some(id), "revoke") = (args.first(), cmd.as_str()) {
+                let result = if sessions::revoke(id) {
+                    Ok(())
+                } else {
+                    Err(anyhow::anyhow!("No session {id}"))
+                };
+                handle_error!(record(actor, "session.revoke", &[id], result));
                 if socket_send
-                    .send(shared::BackendData::Service(shared::ServiceList {
-                        services: handle_error!(systemdata::services().await, Vec::new()),
+                    .send(shared::BackendData::Sessions(shared::SessionList {
+                        sessions: sessions::list(),
                     }))
                     .await
                     .is_err()
@@ -254,6 +347,109 @@ pub async fn service_handler(socket_send: &mut SocketSend, data_recv: &mut RecvC
     false
 }
 
+fn api_token_list(created: Option<String>) -> shared::BackendData {
+    shared::BackendData::ApiTokens(shared::ApiTokenList {
+        tokens: handle_error!(apitokens::list(), Vec::new()),
+        created,
+    })
+}
+
+// Creates the token, and returns it so it can be shown once
+fn api_token_create(args: &[String]) -> anyhow::Result<String> {
+    let [name, days, scopes @ ..] = args else {
+        anyhow::bail!("Not enough arguments to create API token");
+    };
+    let days = if days.is_empty() {
+        0
+    } else {
+        days.parse().context("Invalid API token expiry")?
+    };
+    let scopes = scopes
+        .iter()
+        .map(|x| shared::Scope::parse(x).with_context(|| format!("Unknown scope {x}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    apitokens::create(name, &scopes, apitokens::expiry_from_days(days))
+}
+
+#[instrument(skip_all)]
+pub async fn tokens_handler(
+    socket_send: &mut SocketSend,
+    data_recv: &mut RecvChannel,
+    actor: &ActorRecv,
+) -> bool {
+    if socket_send.send(api_token_list(None)).await.is_err() {
+        tracing::debug!("Socket send failed, returning");
+        return true;
+    }
+    while let Some(Some(data)) = data_recv.recv().await {
+        if let RequestTypes::Cmd {
+            cmd,
+            args: Some(args),
+        } = data
+        {
+            let created = match (cmd.as_str(), args.as_slice()) {
+                ("create", args) => {
+                    let result = api_token_create(args);
+                    let result = record(actor, "token.create", args, result);
+                    handle_error!(result.map(Some), None)
+                }
+                ("revoke", [name]) => {
+                    let result = apitokens::revoke(name).and_then(|existed| {
+                        anyhow::ensure!(existed, "No API token {name}");
+                        Ok(())
+                    });
+                    handle_error!(record(actor, "token.revoke", &[name], result));
+                    None
+                }
+                _ => continue,
+            };
+            if socket_send.send(api_token_list(created)).await.is_err() {
+                tracing::debug!("Socket send failed, returning");
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn audit_list(filter: &audit::Filter) -> shared::BackendData {
+    shared::BackendData::Audit(shared::AuditList {
+        entries: handle_error!(audit::query(filter), Vec::new()),
+    })
+}
+
+#[instrument(skip_all)]
+pub async fn audit_handler(socket_send: &mut SocketSend, data_recv: &mut RecvChannel) -> bool {
+    if socket_send
+        .send(audit_list(&audit::Filter::default()))
+        .await
+        .is_err()
+    {
+        tracing::debug!("Socket send failed, returning");
+        return true;
+    }
+    while let Some(Some(data)) = data_recv.recv().await {
+        if let RequestTypes::Cmd {
+            cmd,
+            args: Some(args),
+        } = data
+        {
+            if cmd != "filter" {
+                continue;
+            }
+            if socket_send
+                .send(audit_list(&audit::Filter::from_args(&args)))
+                .await
+                .is_err()
+            {
+                tracing::debug!("Socket send failed, returning");
+                return true;
+            }
+        }
+    }
+    false
+}
+
 async fn browser_refresh(path: &std::path::Path) -> anyhow::Result<shared::BrowserList> {
     let dir_path = path
         .parent()
@@ -324,7 +520,11 @@ async fn browser_handler_helper(cmd: &str, args: &[String]) -> anyhow::Result<sh
 }
 
 #[instrument(skip_all)]
-pub async fn browser_handler(socket_send: &mut SocketSend, data_recv: &mut RecvChannel) -> bool {
+pub async fn browser_handler(
+    socket_send: &mut SocketSend,
+    data_recv: &mut RecvChannel,
+    actor: &ActorRecv,
+) -> bool {
     // Get initial listing of $HOME
     if socket_send
         .send(shared::BackendData::Browser(shared::BrowserList {
@@ -344,25 +544,75 @@ pub async fn browser_handler(socket_send: &mut SocketSend, data_recv: &mut RecvC
     }
 
     'outer: while let Some(Some(mut data)) = data_recv.recv().await {
-        loop {
-            if let RequestTypes::Cmd {
-                cmd,
-                args: Some(args),
-            } = &data
-            {
-                tokio::select! {
-                    res = browser_handler_helper(cmd, args) => {
-                        if socket_send.send(shared::BackendData::Browser(handle_error!(res, shared::BrowserList::default()))).await.is_err() {
-                            tracing::debug!("Socket send failed, returning");
-                            return true;
-                        }
-                        break;
-                    },
-                    recv = data_recv.recv() => match recv {
-                        Some(Some(data_tmp)) => data = data_tmp,
-                        _ => break 'outer,
-                    },
-                }
+        // Anything else goes back to waiting for the next request, instead of looping without ever waiting
+        while let RequestTypes::Cmd {
+            cmd,
+            args: Some(args),
+        } = &data
+        {
+            tokio::select! {
+                res = browser_handler_helper(cmd, args) => {
+                    // Listing directories doesn't change anything
+                    let res = if cmd == "cd" {
+                        res
+                    } else {
+                        record(actor, &format!("browser.{cmd}"), args, res)
+                    };
+                    if socket_send.send(shared::BackendData::Browser(handle_error!(res, shared::BrowserList::default()))).await.is_err() {
+                        tracing::debug!("Socket send failed, returning");
+                        return true;
+                    }
+                    break;
+                },
+                recv = data_recv.recv() => match recv {
+                    Some(Some(data_tmp)) => data = data_tmp,
+                    _ => break 'outer,
+                },
+            }
+        }
+    }
+    false
+}
+
+fn recording_list(open: Option<shared::RecordingData>) -> shared::BackendData {
+    shared::BackendData::Recordings(shared::RecordingList {
+        recordings: handle_error!(recordings::list(), Vec::new()),
+        open,
+    })
+}
+
+#[instrument(skip_all)]
+pub async fn recordings_handler(
+    socket_send: &mut SocketSend,
+    data_recv: &mut RecvChannel,
+    actor: &ActorRecv,
+) -> bool {
+    if socket_send.send(recording_list(None)).await.is_err() {
+        tracing::debug!("Socket send failed, returning");
+        return true;
+    }
+    while let Some(Some(data)) = data_recv.recv().await {
+        if let RequestTypes::Cmd {
+            cmd,
+            args: Some(args),
+        } = data
+        {
+            let (Some(name), "open") = (args.first(), cmd.as_str()) else {
+                continue;
+            };
+            let result = recordings::read(name);
+            let open = handle_error!(
+                record(actor, "recording.open", &[name], result).map(|data| Some(
+                    shared::RecordingData {
+                        name: name.clone(),
+                        data,
+                    }
+                )),
+                None
+            );
+            if socket_send.send(recording_list(open)).await.is_err() {
+                tracing::debug!("Socket send failed, returning");
+                return true;
             }
         }
     }
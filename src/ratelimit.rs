@@ -0,0 +1,121 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::shared::CONFIG;
+
+// Window used for the global limit, in seconds
+const GLOBAL_WINDOW_SECS: u64 = 60;
+const GLOBAL_WINDOW: Duration = Duration::from_secs(GLOBAL_WINDOW_SECS);
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    blocked_until: Instant,
+}
+
+#[derive(Default)]
+struct Limiter {
+    addrs: HashMap<IpAddr, Failures>,
+    // Times of the most recent failures from any address, oldest first
+    global: VecDeque<Instant>,
+}
+
+static LIMITER: LazyLock<Mutex<Limiter>> = LazyLock::new(|| Mutex::new(Limiter::default()));
+
+// IPv4 clients show up as IPv6-mapped addresses, since the server listens on [::]
+const fn key(addr: IpAddr) -> IpAddr {
+    addr.to_canonical()
+}
+
+fn global_wait(limiter: &Limiter, now: Instant) -> Option<Duration> {
+    let limit = CONFIG.login_global_limit as usize;
+    if limit == 0 || limiter.global.len() < limit {
+        return None;
+    }
+    // The oldest failure that still counts towards the limit decides when the next attempt is allowed
+    let oldest = limiter.global[limiter.global.len() - limit];
+    (oldest + GLOBAL_WINDOW)
+        .checked_duration_since(now)
+        .filter(|x| !x.is_zero())
+}
+
+// How long the client has to wait before trying again, if it can't try now
+pub fn check(addr: IpAddr) -> Option<Duration> {
+    let limiter = LIMITER.lock().unwrap_or_else(PoisonError::into_inner);
+    let now = Instant::now();
+
+    limiter
+        .addrs
+        .get(&key(addr))
+        .and_then(|x| x.blocked_until.checked_duration_since(now))
+        .filter(|x| !x.is_zero())
+        .max(global_wait(&limiter, now))
+}
+
+// Returns how long the client now has to wait before its next attempt
+pub fn failure(addr: IpAddr) -> Option<Duration> {
+    let mut limiter = LIMITER.lock().unwrap_or_else(PoisonError::into_inner);
+    let now = Instant::now();
+    let lockout = Duration::from_secs(CONFIG.login_lockout_time);
+
+    // Forget addresses that haven't failed for a while, so the map can't grow forever
+    limiter
+        .addrs
+        .retain(|_, x| now.duration_since(x.last) < lockout.max(GLOBAL_WINDOW));
+
+    let failures = limiter.addrs.entry(key(addr)).or_insert(Failures {
+        count: 0,
+        last: now,
+        blocked_until: now,
+    });
+    failures.count += 1;
+    failures.last = now;
+
+    let wait = if CONFIG.login_lockout_after != 0 && failures.count >= CONFIG.login_lockout_after {
+        tracing::warn!(
+            "Locked out {} for {} seconds after {} failed login attempts",
+            addr,
+            lockout.as_secs(),
+            failures.count
+        );
+        lockout
+    } else if failures.count > CONFIG.login_backoff_after {
+        // Double the wait for every further failure
+        let exponent = (failures.count - CONFIG.login_backoff_after - 1).min(63);
+        Duration::from_secs(
+            1u64.checked_shl(exponent)
+                .unwrap_or(u64::MAX)
+                .min(CONFIG.login_backoff_max),
+        )
+    } else {
+        Duration::ZERO
+    };
+    failures.blocked_until = now + wait;
+
+    let limit = CONFIG.login_global_limit as usize;
+    if limit != 0 {
+        limiter.global.push_back(now);
+        while limiter.global.len() > limit
+            || limiter
+                .global
+                .front()
+                .is_some_and(|x| now.duration_since(*x) >= GLOBAL_WINDOW)
+        {
+            limiter.global.pop_front();
+        }
+    }
+
+    let global = global_wait(&limiter, now);
+    drop(limiter);
+    Some(wait).filter(|x| !x.is_zero()).max(global)
+}
+
+pub fn success(addr: IpAddr) {
+    LIMITER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .addrs
+        .remove(&key(addr));
+}
@@ -0,0 +1,240 @@
+use anyhow::Context;
+use std::io::{BufRead, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::shared::{self, CONFIG};
+use crate::{audit, config, handle_error};
+
+// Inside the data directory, one asciicast v2 file per terminal
+const DIR: &str = "recordings";
+
+enum Event {
+    Output(Vec<u8>),
+    Input(Vec<u8>),
+    Resize(u16, u16),
+    Marker(String),
+}
+
+// Hands events over to a thread of its own that writes them, so that terminals never wait for the disk
+// The thread finishes once this is dropped
+pub struct Recorder {
+    sender: std::sync::mpsc::Sender<(Instant, Event)>,
+}
+
+impl Recorder {
+    fn send(&self, event: Event) {
+        // Only fails once writing has failed, which has been logged already
+        let _ = self.sender.send((Instant::now(), event));
+    }
+
+    pub fn output(&self, data: &[u8]) {
+        self.send(Event::Output(data.to_vec()));
+    }
+
+    pub fn input(&self, data: &[u8]) {
+        self.send(Event::Input(data.to_vec()));
+    }
+
+    pub fn resize(&self, cols: u16, rows: u16) {
+        self.send(Event::Resize(cols, rows));
+    }
+
+    pub fn marker(&self, label: &str) {
+        self.send(Event::Marker(label.to_string()));
+    }
+}
+
+// Events are written as they come, see https://docs.asciinema.org/manual/asciicast/v2/
+struct Recording {
+    file: std::io::LineWriter<std::fs::File>,
+    start: Instant,
+    // Start of a UTF-8 character that was split between two reads
+    output_rest: Vec<u8>,
+    input_rest: Vec<u8>,
+}
+
+// Decodes as much as possible, keeping an incomplete character at the end for the next call
+fn utf8(rest: &mut Vec<u8>, data: &[u8]) -> String {
+    rest.extend_from_slice(data);
+    let valid = match std::str::from_utf8(rest) {
+        Err(err) if err.error_len().is_none() => err.valid_up_to(),
+        _ => rest.len(),
+    };
+    let text = String::from_utf8_lossy(&rest[..valid]).into_owned();
+    rest.drain(..valid);
+    text
+}
+
+// The file is created straight away, so that the terminal isn't opened if it can't be recorded
+// The size is the pty's when it starts, changes are recorded as they happen
+pub fn start(
+    id: &str,
+    name: &str,
+    actor: &audit::Actor,
+    cols: u16,
+    rows: u16,
+) -> anyhow::Result<Recorder> {
+    prune();
+    let dir = config::data_path(DIR);
+    // Not readable by others, since typed passwords end up in there
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("Couldn't create recordings directory {}", dir.display()))?;
+    let timestamp = jsonwebtoken::get_current_timestamp();
+    let path = dir.join(format!("{timestamp}-{id}.cast"));
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .with_context(|| format!("Couldn't create recording {}", path.display()))?;
+    let mut recording = Recording {
+        file: std::io::LineWriter::new(file),
+        start: Instant::now(),
+        output_rest: Vec::new(),
+        input_rest: Vec::new(),
+    };
+    // "user" and "addr" aren't part of asciicast, players ignore them
+    let header = serde_json::json!({
+        "version": 2,
+        "width": cols,
+        "height": rows,
+        "timestamp": timestamp,
+        "title": name,
+        "env": { "TERM": CONFIG.terminal_term },
+        "user": actor.user,
+        "addr": actor.addr.to_string(),
+    });
+    writeln!(recording.file, "{header}")
+        .with_context(|| format!("Couldn't write to recording {}", path.display()))?;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::Builder::new()
+        .name("recording".to_string())
+        .spawn(move || {
+            for (time, event) in receiver {
+                if let Err(err) = recording.write(time, event) {
+                    tracing::error!("Couldn't write to terminal recording, stopping it: {}", err);
+                    break;
+                }
+            }
+        })
+        .context("Couldn't start recording thread")?;
+    Ok(Recorder { sender })
+}
+
+impl Recording {
+    fn write(&mut self, time: Instant, event: Event) -> std::io::Result<()> {
+        let (kind, data) = match event {
+            Event::Output(data) => ("o", utf8(&mut self.output_rest, &data)),
+            Event::Input(data) => ("i", utf8(&mut self.input_rest, &data)),
+            Event::Resize(cols, rows) => ("r", format!("{cols}x{rows}")),
+            Event::Marker(label) => ("m", label),
+        };
+        if data.is_empty() {
+            return Ok(());
+        }
+        let time = (time.duration_since(self.start).as_secs_f64() * 1e6).round() / 1e6;
+        writeln!(self.file, "{}", serde_json::json!([time, kind, data]))
+    }
+}
+
+fn is_recording(name: &str) -> bool {
+    std::path::Path::new(name)
+        .extension()
+        .is_some_and(|x| x == "cast")
+        && name
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '.')
+}
+
+fn files() -> anyhow::Result<Vec<(String, std::fs::Metadata)>> {
+    let dir = config::data_path(DIR);
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        // Nothing has been recorded yet
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Couldn't read recordings directory {}", dir.display()))
+        }
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry.context("Couldn't read recordings directory entry")?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !is_recording(&name) {
+            continue;
+        }
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("Couldn't get metadata of recording {name}"))?;
+        files.push((name, metadata));
+    }
+    Ok(files)
+}
+
+// Removes recordings that are older than configured
+pub fn prune() {
+    if CONFIG.terminal_recording_days == 0 {
+        return;
+    }
+    let max_age = Duration::from_secs(CONFIG.terminal_recording_days * 24 * 60 * 60);
+    for (name, metadata) in handle_error!(files(), return) {
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|x| SystemTime::now().duration_since(x).ok());
+        if age.is_some_and(|x| x > max_age) {
+            tracing::info!("Removing old terminal recording {}", name);
+            handle_error!(std::fs::remove_file(config::data_path(DIR).join(&name))
+                .with_context(|| format!("Couldn't remove recording {name}")));
+        }
+    }
+}
+
+pub fn list() -> anyhow::Result<Vec<shared::RecordingInfo>> {
+    prune();
+    let mut list = Vec::new();
+    for (name, metadata) in files()? {
+        let path = config::data_path(DIR).join(&name);
+        let mut header = String::new();
+        handle_error!(
+            std::fs::File::open(&path)
+                .and_then(|file| std::io::BufReader::new(file).read_line(&mut header))
+                .with_context(|| format!("Couldn't read recording {name}")),
+            continue
+        );
+        let header: serde_json::Value = handle_error!(
+            serde_json::from_str(&header)
+                .with_context(|| format!("Invalid header in recording {name}")),
+            continue
+        );
+        let time = header["timestamp"].as_u64().unwrap_or_default();
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|x| x.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(time, |x| x.as_secs());
+        list.push(shared::RecordingInfo {
+            title: header["title"].as_str().unwrap_or_default().to_string(),
+            user: header["user"].as_str().map(ToString::to_string),
+            addr: header["addr"].as_str().unwrap_or_default().to_string(),
+            time,
+            duration: modified.saturating_sub(time),
+            size: metadata.len(),
+            name,
+        });
+    }
+    list.sort_unstable_by_key(|x| std::cmp::Reverse(x.time));
+    Ok(list)
+}
+
+pub fn read(name: &str) -> anyhow::Result<String> {
+    anyhow::ensure!(is_recording(name), "Invalid recording name {name}");
+    std::fs::read_to_string(config::data_path(DIR).join(name))
+        .with_context(|| format!("Couldn't read recording {name}"))
+}
@@ -5,6 +5,7 @@ use futures_util::Future;
 use hyper::http::{header, HeaderValue};
 use hyper::{Body, Method, Request, Response, StatusCode};
 use ring::digest;
+use subtle::ConstantTimeEq;
 use tracing::Instrument;
 
 #[cfg(feature = "dev")]
@@ -105,8 +106,27 @@ fn asset_route(path: &str) -> Response<Body> {
     reply
 }
 
+fn too_many_attempts(mut response: Response<Body>, wait: std::time::Duration) -> Response<Body> {
+    // Round up, so clients don't retry just before the wait is over
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, secs.into());
+    // Other nodes' frontends need to read it too
+    response.headers_mut().insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static("Retry-After"),
+    );
+    *response.body_mut() = "Too many login attempts".into();
+    response
+}
+
 #[tracing::instrument(skip_all)]
-pub async fn login_route(mut req: Request<Body>) -> anyhow::Result<Response<Body>> {
+pub async fn login_route(
+    mut req: Request<Body>,
+    remote_ip: std::net::IpAddr,
+) -> anyhow::Result<Response<Body>> {
     let token: String;
     let mut response = Response::new(Body::empty());
     response.headers_mut().insert(
@@ -114,14 +134,44 @@ pub async fn login_route(mut req: Request<Body>) -> anyhow::Result<Response<Body
         HeaderValue::from_static("*"),
     );
     if CONFIG.pass {
-        let shasum = hex::encode(
-            digest::digest(
-                &digest::SHA512,
-                &hyper::body::to_bytes(req.body_mut()).await?,
-            )
-            .as_ref(),
-        );
-        if shasum == CONFIG.hash {
+        // Checked before the password, so that blocked clients can't make the server hash anything
+        if let Some(wait) = crate::ratelimit::check(remote_ip) {
+            tracing::info!("Login attempt blocked for {} more seconds", wait.as_secs());
+            return Ok(too_many_attempts(response, wait));
+        }
+        let login = crate::auth::LoginRequest::parse(&hyper::body::to_bytes(req.body_mut()).await?);
+        if let Some((user, role)) = crate::auth::authenticate(&login).await {
+            // The password is right, but no token is given out until the second factor is too
+            // Reads and writes the two-factor file, so it's kept off the runtime thread
+            let check = {
+                let user = user.clone();
+                let code = login.code.clone();
+                handle_error!(
+                    tokio::task::spawn_blocking(move || crate::totp::check(&user, &code))
+                        .await
+                        .context("Couldn't check two-factor code"),
+                    crate::totp::Check::Invalid
+                )
+            };
+            match check {
+                crate::totp::Check::Passed => {}
+                crate::totp::Check::Missing => {
+                    *response.status_mut() = StatusCode::UNAUTHORIZED;
+                    *response.body_mut() = "Two-factor code required".into();
+                    return Ok(response);
+                }
+                crate::totp::Check::Invalid => {
+                    tracing::warn!("Invalid two-factor code for user {}", user);
+                    if let Some(wait) = crate::ratelimit::failure(remote_ip) {
+                        return Ok(too_many_attempts(response, wait));
+                    }
+                    *response.status_mut() = StatusCode::UNAUTHORIZED;
+                    *response.body_mut() = "Invalid two-factor code".into();
+                    return Ok(response);
+                }
+            }
+            tracing::info!("User {} logged in", user);
+            crate::ratelimit::success(remote_ip);
             let timestamp = jsonwebtoken::get_current_timestamp();
 
             let fingerprint = match crate::shared::get_fingerprint(&req) {
@@ -153,20 +203,39 @@ pub async fn login_route(mut req: Request<Body>) -> anyhow::Result<Response<Body
                 }
             };
 
+            let user_agent = req
+                .headers()
+                .get(header::USER_AGENT)
+                .map_or("unknown", |x| x.to_str().unwrap_or("unknown"));
+            let (jti, refresh) = handle_error!(
+                crate::sessions::create(
+                    &user,
+                    role,
+                    remote_ip,
+                    user_agent,
+                    &fingerprint,
+                    timestamp,
+                    timestamp + CONFIG.expiry
+                ),
+                return Ok({
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    *response.body_mut() = "Couldn't create session".into();
+                    response
+                })
+            );
+
             let claims = crate::shared::JWTClaims {
                 iss: "DietPi Dashboard".to_string(),
                 iat: timestamp,
                 exp: timestamp + CONFIG.expiry,
                 fingerprint,
+                user,
+                role,
+                jti,
             };
 
             token = handle_error!(
-                jsonwebtoken::encode(
-                    &jsonwebtoken::Header::default(),
-                    &claims,
-                    &jsonwebtoken::EncodingKey::from_secret(CONFIG.secret.as_ref()),
-                )
-                .context("Error creating login token"),
+                encode_token(&claims),
                 return Ok({
                     *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
                     *response.body_mut() = "Couldn't create login token".into();
@@ -174,10 +243,24 @@ pub async fn login_route(mut req: Request<Body>) -> anyhow::Result<Response<Body
                 })
             );
 
+            response
+                .headers_mut()
+                .append(hyper::header::SET_COOKIE, refresh_cookie(&refresh)?);
             *response.body_mut() = token.into();
 
             return Ok(response);
         }
+        tracing::warn!(
+            "Failed login attempt for user {:?}",
+            if login.username.is_empty() {
+                crate::auth::LEGACY_USER
+            } else {
+                &login.username
+            }
+        );
+        if let Some(wait) = crate::ratelimit::failure(remote_ip) {
+            return Ok(too_many_attempts(response, wait));
+        }
         *response.status_mut() = StatusCode::UNAUTHORIZED;
         *response.body_mut() = "Invalid password".into();
         return Ok(response);
@@ -187,11 +270,148 @@ pub async fn login_route(mut req: Request<Body>) -> anyhow::Result<Response<Body
     Ok(response)
 }
 
+fn encode_token(claims: &crate::shared::JWTClaims) -> anyhow::Result<String> {
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        claims,
+        &jsonwebtoken::EncodingKey::from_secret(CONFIG.secret.as_ref()),
+    )
+    .context("Error creating login token")
+}
+
+// Only sent to "/refresh", and out of reach of scripts on the page
+fn refresh_cookie(token: &str) -> anyhow::Result<HeaderValue> {
+    HeaderValue::from_str(&format!(
+        "REFRESH={token}; Path=/refresh; Max-Age={}; HttpOnly; SameSite=Strict",
+        CONFIG.session_lifetime.max(CONFIG.expiry)
+    ))
+    .context("Couldn't set refresh token")
+}
+
+// Claims of the valid token in the "Authorization" header, if there is one
+fn bearer_claims(req: &Request<Body>) -> Option<crate::shared::JWTClaims> {
+    let token = crate::shared::get_bearer_token(req)?;
+    let fingerprint = crate::shared::get_fingerprint(req).ok().flatten();
+    match crate::socket_handlers::validate_token(token, fingerprint.as_deref()) {
+        crate::socket_handlers::TokenState::ValidToken(claims) => Some(claims),
+        _ => None,
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn refresh_route(req: &Request<Body>) -> anyhow::Result<Response<Body>> {
+    let mut response = Response::new(Body::empty());
+    response.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    // The fingerprint cookie is checked too, so a stolen refresh token can't be used elsewhere
+    let cookies = req
+        .headers()
+        .get(header::COOKIE)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default();
+    let token = crate::shared::get_token_from_list(cookies, ['=', ';'], "REFRESH");
+    let fingerprint = crate::shared::get_fingerprint(req).ok().flatten();
+    let (Some(token), Some(fingerprint)) = (token, fingerprint) else {
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        *response.body_mut() = "Invalid refresh token".into();
+        return Ok(response);
+    };
+    let refreshed = handle_error!(
+        crate::sessions::refresh(token, &fingerprint),
+        return Ok({
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            *response.body_mut() = "Couldn't refresh session".into();
+            response
+        })
+    );
+    let Some(refreshed) = refreshed else {
+        tracing::info!("Session has ended or reached its maximum lifetime");
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        *response.body_mut() = "Session expired".into();
+        return Ok(response);
+    };
+    let claims = crate::shared::JWTClaims {
+        iss: "DietPi Dashboard".to_string(),
+        iat: jsonwebtoken::get_current_timestamp(),
+        exp: refreshed.expires,
+        fingerprint: refreshed.fingerprint,
+        user: refreshed.user,
+        role: refreshed.role,
+        jti: refreshed.id,
+    };
+    response
+        .headers_mut()
+        .insert(header::SET_COOKIE, refresh_cookie(&refreshed.refresh)?);
+
+    *response.body_mut() = handle_error!(
+        encode_token(&claims),
+        return Ok({
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            *response.body_mut() = "Couldn't create login token".into();
+            response
+        })
+    )
+    .into();
+    Ok(response)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn logout_route(req: &Request<Body>) -> anyhow::Result<Response<Body>> {
+    let mut response = Response::new(Body::empty());
+    response.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    if let Some(claims) = bearer_claims(req) {
+        crate::sessions::revoke(&claims.jti);
+        tracing::info!("User {} logged out", claims.user);
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        response.headers_mut().insert(
+            header::SET_COOKIE,
+            HeaderValue::from_static(
+                "REFRESH=; Path=/refresh; Max-Age=0; HttpOnly; SameSite=Strict",
+            ),
+        );
+    } else {
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        *response.body_mut() = "Invalid token".into();
+    }
+    Ok(response)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn metrics_route(req: Request<Body>) -> anyhow::Result<Response<Body>> {
+    if !CONFIG.metrics_token.is_empty()
+        && !crate::shared::get_bearer_token(&req).is_some_and(|token| {
+            token
+                .as_bytes()
+                .ct_eq(CONFIG.metrics_token.as_bytes())
+                .into()
+        })
+    {
+        tracing::info!("Invalid metrics token");
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .body("Invalid token".into())?);
+    }
+
+    Ok(Response::builder()
+        .header(
+            header::CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )
+        .body(crate::metrics::render().await.into())?)
+}
+
 pub fn websocket<F, O>(
     mut req: Request<Body>,
     func: F,
     span: tracing::Span,
     token: String,
+    remote_addr: std::net::SocketAddr,
 ) -> anyhow::Result<Response<Body>>
 where
     O: Future<Output = ()> + std::marker::Send,
@@ -199,6 +419,7 @@ where
             tokio_tungstenite::WebSocketStream<hyper::upgrade::Upgraded>,
             Option<String>,
             String,
+            std::net::IpAddr,
         ) -> O
         + std::marker::Send
         + std::marker::Sync
@@ -252,7 +473,9 @@ where
                     None,
                 )
                 .await;
-                func(ws, cookie, token).instrument(span).await;
+                func(ws, cookie, token, remote_addr.ip())
+                    .instrument(span)
+                    .await;
             }
             Err(e) => eprintln!("upgrade error: {e}"),
         }
@@ -260,7 +483,11 @@ where
     Ok(resp?)
 }
 
-pub async fn router(req: Request<Body>, span: tracing::Span) -> anyhow::Result<Response<Body>> {
+pub async fn router(
+    req: Request<Body>,
+    span: tracing::Span,
+    remote_addr: std::net::SocketAddr,
+) -> anyhow::Result<Response<Body>> {
     let mut response = Response::new(Body::empty());
 
     let mut path = req.uri().path();
@@ -290,6 +517,7 @@ pub async fn router(req: Request<Body>, span: tracing::Span) -> anyhow::Result<R
                 crate::socket_handlers::socket_handler,
                 span,
                 String::new(),
+                remote_addr,
             )?;
         }
         (&Method::GET, "/ws/term", Some(token)) if crate::CONFIG.pass => {
@@ -300,6 +528,23 @@ pub async fn router(req: Request<Body>, span: tracing::Span) -> anyhow::Result<R
                     crate::socket_handlers::term_handler,
                     span,
                     token.to_string(),
+                    remote_addr,
+                )?;
+            } else {
+                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                *response.body_mut() = "No token".into();
+                return Ok(response);
+            }
+        }
+        (&Method::GET, "/ws/term/view", Some(token)) if crate::CONFIG.pass => {
+            let token = crate::shared::get_token_from_list(&token, ['=', ';'], "token");
+            if let Some(token) = token {
+                response = websocket(
+                    req,
+                    crate::socket_handlers::term_view_handler,
+                    span,
+                    token.to_string(),
+                    remote_addr,
                 )?;
             } else {
                 *response.status_mut() = StatusCode::UNAUTHORIZED;
@@ -315,6 +560,7 @@ pub async fn router(req: Request<Body>, span: tracing::Span) -> anyhow::Result<R
                     crate::socket_handlers::file_handler,
                     span,
                     token.to_string(),
+                    remote_addr,
                 )?;
             } else {
                 *response.status_mut() = StatusCode::UNAUTHORIZED;
@@ -328,6 +574,16 @@ pub async fn router(req: Request<Body>, span: tracing::Span) -> anyhow::Result<R
                 crate::socket_handlers::term_handler,
                 span,
                 String::new(),
+                remote_addr,
+            )?;
+        }
+        (&Method::GET, "/ws/term/view", _) if !crate::CONFIG.pass => {
+            response = websocket(
+                req,
+                crate::socket_handlers::term_view_handler,
+                span,
+                String::new(),
+                remote_addr,
             )?;
         }
         (&Method::GET, "/ws/file", _) if !crate::CONFIG.pass => {
@@ -336,10 +592,27 @@ pub async fn router(req: Request<Body>, span: tracing::Span) -> anyhow::Result<R
                 crate::socket_handlers::file_handler,
                 span,
                 String::new(),
+                remote_addr,
             )?;
         }
         (&Method::POST, "/login", _) => {
-            response = login_route(req).instrument(span).await?;
+            response = login_route(req, remote_addr.ip()).instrument(span).await?;
+        }
+        (&Method::POST, "/refresh", _) if CONFIG.pass => {
+            let _guard = span.enter();
+            response = refresh_route(&req)?;
+        }
+        (&Method::POST, "/logout", _) if CONFIG.pass => {
+            let _guard = span.enter();
+            response = logout_route(&req)?;
+        }
+        (&Method::GET, "/metrics", _) if CONFIG.metrics => {
+            response = metrics_route(req).instrument(span).await?;
+        }
+        (_, path, _) if path.starts_with("/api/v1/") => {
+            response = crate::api::api_route(req, remote_addr.ip())
+                .instrument(span)
+                .await?;
         }
         #[cfg(feature = "dev")]
         (&Method::GET, "/", _) => {
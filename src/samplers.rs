@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+use tracing::instrument;
+
+use crate::shared::{self, CONFIG};
+use crate::{handle_error, page_handlers, systemdata};
+
+#[derive(Default)]
+struct State {
+    running: bool,
+    // Interval wanted by each subscription, the sampler goes as fast as the fastest one
+    intervals: HashMap<u64, Duration>,
+    next_id: u64,
+}
+
+enum Sample<T> {
+    // Before the first sample, and after the sampler stopped for lack of subscribers
+    Empty,
+    Value(T),
+    // The sampler couldn't start, so there won't be a sample until someone subscribes again
+    Failed,
+}
+
+// Latest sample of one kind of data, shared by everyone who wants it
+// The sampler only runs while something is subscribed, so nothing is measured while nobody is looking
+pub struct Sampler<T> {
+    sender: watch::Sender<Sample<T>>,
+    state: Mutex<State>,
+    // Lets a long wait be cut short when a subscription wants data sooner
+    interval_changed: Notify,
+    default: Duration,
+}
+
+// Kept within the configured bounds, so the sampler can't spin
+// Never 0 either, in case the bounds themselves allow it
+fn bounded(millis: u64) -> Duration {
+    Duration::from_millis(
+        millis
+            .max(CONFIG.interval_min)
+            .min(CONFIG.interval_max)
+            .max(1),
+    )
+}
+
+impl<T: Send + Sync> Sampler<T> {
+    fn new(default_millis: u64) -> Self {
+        Self {
+            sender: watch::Sender::new(Sample::Empty),
+            state: Mutex::default(),
+            interval_changed: Notify::new(),
+            default: bounded(default_millis),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn interval(&self) -> Duration {
+        self.state()
+            .intervals
+            .values()
+            .min()
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    fn publish(&self, value: T) {
+        self.sender.send_replace(Sample::Value(value));
+    }
+
+    // For samplers that can't run at all, so the next subscriber tries again
+    // Everyone waiting for a sample is told that there won't be one
+    fn stop(&self) {
+        let mut state = self.state();
+        state.running = false;
+        self.sender.send_replace(Sample::Failed);
+        drop(state);
+    }
+
+    // Waits until the next sample is due, and returns false if the sampler should stop
+    async fn wait(&self, last: Instant) -> bool {
+        loop {
+            tokio::select! {
+                () = tokio::time::sleep_until(last + self.interval()) => break,
+                () = self.interval_changed.notified() => {}
+                () = self.sender.closed() => break,
+            }
+        }
+        // Checked with the lock held, so a new subscriber either sees the sampler running or starts a new one
+        let mut state = self.state();
+        let stop = self.sender.receiver_count() == 0;
+        if stop {
+            state.running = false;
+            // Don't give the next subscriber old data
+            self.sender.send_replace(Sample::Empty);
+        }
+        drop(state);
+        !stop
+    }
+}
+
+impl<T: Send + Sync + 'static> Sampler<T> {
+    // Starts the sampler if it isn't running yet
+    fn subscribe<F>(&'static self, run: fn() -> F) -> Subscription<T>
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let mut state = self.state();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.intervals.insert(id, self.default);
+        // A failed start is tried again
+        if !state.running && matches!(*self.sender.borrow(), Sample::Failed) {
+            self.sender.send_replace(Sample::Empty);
+        }
+        let mut receiver = self.sender.subscribe();
+        // The last sample (if there is one) counts as new, so it's sent straight away
+        receiver.mark_changed();
+        if !state.running {
+            state.running = true;
+            tokio::spawn(run());
+        }
+        drop(state);
+        self.interval_changed.notify_one();
+        Subscription {
+            sampler: self,
+            id,
+            interval: self.default,
+            next: Instant::now(),
+            receiver,
+        }
+    }
+}
+
+pub struct Subscription<T: 'static> {
+    sampler: &'static Sampler<T>,
+    id: u64,
+    interval: Duration,
+    next: Instant,
+    receiver: watch::Receiver<Sample<T>>,
+}
+
+impl<T: Clone + Send + Sync> Subscription<T> {
+    // Kept within the configured bounds, so one client can't make the sampler spin
+    // 0 goes back to the default
+    pub fn set_interval(&mut self, millis: u64) {
+        let interval = if millis == 0 {
+            self.sampler.default
+        } else {
+            bounded(millis)
+        };
+        tracing::debug!("Setting sampling interval to {:?}", interval);
+        self.interval = interval;
+        self.next = self.next.min(Instant::now() + interval);
+        self.sampler.state().intervals.insert(self.id, interval);
+        self.sampler.interval_changed.notify_one();
+    }
+
+    // Waits for a new sample, but no sooner than the subscription's interval after the last one
+    // Returns None if the sampler couldn't start
+    pub async fn next(&mut self) -> Option<T> {
+        tokio::time::sleep_until(self.next).await;
+        loop {
+            self.receiver.changed().await.ok()?;
+            match &*self.receiver.borrow_and_update() {
+                Sample::Value(value) => {
+                    self.next = Instant::now() + self.interval;
+                    return Some(value.clone());
+                }
+                Sample::Failed => return None,
+                Sample::Empty => {}
+            }
+        }
+    }
+}
+
+impl<T: 'static> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.sampler
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .intervals
+            .remove(&self.id);
+    }
+}
+
+static STATISTICS: LazyLock<Sampler<shared::SysData>> =
+    LazyLock::new(|| Sampler::new(CONFIG.statistics_interval));
+static PROCESSES: LazyLock<Sampler<Vec<shared::ProcessData>>> =
+    LazyLock::new(|| Sampler::new(CONFIG.process_interval));
+
+pub fn statistics() -> Subscription<shared::SysData> {
+    STATISTICS.subscribe(statistics_sampler)
+}
+
+// For history and alert rules, which keep sampling while nobody is looking, so they do it less often
+pub fn background() -> Subscription<shared::SysData> {
+    let mut subscription = statistics();
+    subscription.set_interval(CONFIG.background_interval);
+    subscription
+}
+
+pub fn processes() -> Subscription<Vec<shared::ProcessData>> {
+    PROCESSES.subscribe(processes_sampler)
+}
+
+#[instrument(skip_all)]
+async fn statistics_sampler() {
+    tracing::debug!("Starting statistics sampler");
+    let mut cpu_collector =
+        handle_error!(systemdata::CpuCollector::new(), return STATISTICS.stop());
+    let mut net_collector = psutil::network::NetIoCountersCollector::default();
+    let mut prev_data = systemdata::network_start(&mut net_collector);
+    let mut prev_disk_io = systemdata::disk_io_start();
+
+    // Usage is measured since the last sample, so the first one is only taken after waiting
+    let mut last = Instant::now();
+    while STATISTICS.wait(last).await {
+        last = Instant::now();
+        STATISTICS.publish(handle_error!(
+            page_handlers::main_handler_getter(
+                &mut cpu_collector,
+                &mut net_collector,
+                &mut prev_data,
+                &mut prev_disk_io
+            ),
+            continue
+        ));
+    }
+    tracing::debug!("No more subscribers, stopping statistics sampler");
+}
+
+#[instrument(skip_all)]
+async fn processes_sampler() {
+    tracing::debug!("Starting process sampler");
+    loop {
+        let last = Instant::now();
+        // Already takes half a second to measure CPU usage
+        handle_error!(systemdata::processes()
+            .await
+            .map(|processes| PROCESSES.publish(processes)));
+        if !PROCESSES.wait(last).await {
+            break;
+        }
+    }
+    tracing::debug!("No more subscribers, stopping process sampler");
+}
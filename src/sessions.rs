@@ -0,0 +1,256 @@
+use anyhow::Context;
+use ring::digest;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::sync::watch;
+use tokio::time::{Instant, Interval};
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::shared::{self, Role, CONFIG};
+
+// Close code telling the frontend that it has to log in again
+pub const RELOGIN_CLOSE_CODE: u16 = 4001;
+
+// How often long-lived sockets check that their session hasn't expired
+const REVALIDATE_INTERVAL: Duration = Duration::from_secs(30);
+
+struct Session {
+    user: String,
+    role: Role,
+    addr: IpAddr,
+    user_agent: String,
+    issued: u64,
+    // Expiry of the newest token, pushed back by refreshes
+    expires: u64,
+    // Refreshing only works from the browser that logged in
+    fingerprint: String,
+    // SHA-256 of the current refresh token, which changes with every refresh
+    refresh: String,
+    // Never sent on, sockets only wait for it to be dropped when the session ends
+    closed: watch::Sender<()>,
+}
+
+// Kept in memory, so restarting the dashboard logs everyone out
+static SESSIONS: LazyLock<Mutex<HashMap<String, Session>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn prune(sessions: &mut HashMap<String, Session>) {
+    let now = jsonwebtoken::get_current_timestamp();
+    sessions.retain(|_, x| x.expires > now);
+}
+
+fn hash(secret: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, secret.as_bytes()))
+}
+
+// Returns the refresh token, made of the session ID and a secret, and the hash of the secret
+fn refresh_token(id: &str) -> anyhow::Result<(String, String)> {
+    let mut buf = [0u8; 32];
+    getrandom::getrandom(&mut buf).context("Couldn't generate random refresh token")?;
+    let secret = hex::encode(buf);
+    Ok((format!("{id}.{secret}"), hash(&secret)))
+}
+
+// Returns the ID to put in the token's "jti" claim, and the refresh token
+pub fn create(
+    user: &str,
+    role: Role,
+    addr: IpAddr,
+    user_agent: &str,
+    fingerprint: &str,
+    issued: u64,
+    expires: u64,
+) -> anyhow::Result<(String, String)> {
+    let mut buf = [0u8; 16];
+    getrandom::getrandom(&mut buf).context("Couldn't generate random session ID")?;
+    let id = hex::encode(buf);
+    let (refresh, refresh_hash) = refresh_token(&id)?;
+
+    let mut sessions = SESSIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    prune(&mut sessions);
+    sessions.insert(
+        id.clone(),
+        Session {
+            user: user.to_string(),
+            role,
+            addr: addr.to_canonical(),
+            user_agent: user_agent.to_string(),
+            issued,
+            expires,
+            fingerprint: fingerprint.to_string(),
+            refresh: refresh_hash,
+            closed: watch::channel(()).0,
+        },
+    );
+    drop(sessions);
+    Ok((id, refresh))
+}
+
+pub fn is_active(id: &str) -> bool {
+    let now = jsonwebtoken::get_current_timestamp();
+    SESSIONS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(id)
+        .is_some_and(|x| x.expires > now)
+}
+
+// Returns whether the session existed
+pub fn revoke(id: &str) -> bool {
+    let session = SESSIONS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(id);
+    if let Some(session) = &session {
+        tracing::info!(
+            "Ended session of user {} from {}",
+            session.user,
+            session.addr
+        );
+    }
+    session.is_some()
+}
+
+pub fn list() -> Vec<shared::SessionInfo> {
+    let mut sessions = SESSIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    prune(&mut sessions);
+    let mut list = sessions
+        .iter()
+        .map(|(id, x)| shared::SessionInfo {
+            id: id.clone(),
+            user: x.user.clone(),
+            role: x.role,
+            addr: x.addr.to_string(),
+            user_agent: x.user_agent.clone(),
+            issued: x.issued,
+            expires: x.expires,
+        })
+        .collect::<Vec<_>>();
+    drop(sessions);
+    list.sort_unstable_by_key(|x| std::cmp::Reverse(x.issued));
+    list
+}
+
+pub struct Refreshed {
+    pub id: String,
+    pub user: String,
+    pub role: Role,
+    pub fingerprint: String,
+    pub expires: u64,
+    // Replaces the one that was used, which can't be used again
+    pub refresh: String,
+}
+
+// Pushes the session's expiry back, up to the absolute lifetime
+// The session expires together with its newest access token, so this has to happen before that
+pub fn refresh(token: &str, fingerprint: &str) -> anyhow::Result<Option<Refreshed>> {
+    let Some((id, secret)) = token.split_once('.') else {
+        return Ok(None);
+    };
+    let (refresh, refresh_hash) = refresh_token(id)?;
+    let now = jsonwebtoken::get_current_timestamp();
+    let mut sessions = SESSIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(session) = sessions.get_mut(id).filter(|x| {
+        x.expires > now
+            && bool::from(x.refresh.as_bytes().ct_eq(hash(secret).as_bytes()))
+            && bool::from(x.fingerprint.as_bytes().ct_eq(fingerprint.as_bytes()))
+    }) else {
+        return Ok(None);
+    };
+    let end = session.issued + CONFIG.session_lifetime.max(CONFIG.expiry);
+    if now >= end {
+        return Ok(None);
+    }
+    session.expires = end.min(now + CONFIG.expiry);
+    session.refresh = refresh_hash;
+    let refreshed = Refreshed {
+        id: id.to_string(),
+        user: session.user.clone(),
+        role: session.role,
+        fingerprint: session.fingerprint.clone(),
+        expires: session.expires,
+        refresh,
+    };
+    drop(sessions);
+    Ok(Some(refreshed))
+}
+
+enum WatchKind {
+    Session { id: String, rx: watch::Receiver<()> },
+    // API tokens don't have sessions, so they're looked up again instead
+    ApiToken(String),
+}
+
+// Kept by each socket, so the interval carries on between messages
+pub struct Watch {
+    kind: WatchKind,
+    interval: Interval,
+}
+
+impl Watch {
+    fn new(kind: WatchKind) -> Self {
+        Self {
+            kind,
+            // The session was just checked, so not straight away
+            interval: tokio::time::interval_at(
+                Instant::now() + REVALIDATE_INTERVAL,
+                REVALIDATE_INTERVAL,
+            ),
+        }
+    }
+
+    pub fn api_token(token: &str) -> Self {
+        Self::new(WatchKind::ApiToken(token.to_string()))
+    }
+}
+
+// For sockets to be closed when their session ends
+pub fn watch(id: &str) -> Option<Watch> {
+    SESSIONS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(id)
+        .map(|x| {
+            Watch::new(WatchKind::Session {
+                id: id.to_string(),
+                rx: x.closed.subscribe(),
+            })
+        })
+}
+
+// Finishes once the session has been revoked or has expired without being refreshed
+// Never finishes without a session (like when there's no login)
+pub async fn ended(watch: &mut Option<Watch>) {
+    let Some(Watch { kind, interval }) = watch else {
+        return std::future::pending().await;
+    };
+    match kind {
+        WatchKind::Session { id, rx } => loop {
+            tokio::select! {
+                changed = rx.changed() => if changed.is_err() {
+                    return;
+                },
+                _ = interval.tick() => if !is_active(id) {
+                    return;
+                },
+            }
+        },
+        WatchKind::ApiToken(token) => loop {
+            interval.tick().await;
+            if crate::apitokens::validate(token).is_none() {
+                return;
+            }
+        },
+    }
+}
+
+pub fn close_message(reason: &str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code: CloseCode::from(RELOGIN_CLOSE_CODE),
+        reason: reason.to_string().into(),
+    }))
+}
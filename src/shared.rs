@@ -63,17 +63,54 @@ pub fn get_fingerprint(req: &hyper::Request<hyper::Body>) -> anyhow::Result<Opti
     ))))
 }
 
+pub fn get_bearer_token(req: &hyper::Request<hyper::Body>) -> Option<&str> {
+    req.headers()
+        .get(hyper::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+// Decode a percent-encoded URL component, like a query parameter
+pub fn percent_decode(encoded: &str) -> anyhow::Result<String> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex_byte = [
+                    bytes.next().context("Incomplete percent encoding")?,
+                    bytes.next().context("Incomplete percent encoding")?,
+                ];
+                decoded.extend(hex::decode(hex_byte).context("Invalid percent encoding")?);
+            }
+            b'+' => decoded.push(b' '),
+            _ => decoded.push(byte),
+        }
+    }
+    String::from_utf8(decoded).context("Percent encoding isn't valid UTF-8")
+}
+
+// Shared, so that alerts can be sent whatever page the socket is on
+#[derive(Clone)]
 pub struct SocketSend(
-    pub  futures_util::stream::SplitSink<
-        tokio_tungstenite::WebSocketStream<hyper::upgrade::Upgraded>,
-        tokio_tungstenite::tungstenite::Message,
+    pub  std::sync::Arc<
+        tokio::sync::Mutex<
+            futures_util::stream::SplitSink<
+                tokio_tungstenite::WebSocketStream<hyper::upgrade::Upgraded>,
+                tokio_tungstenite::tungstenite::Message,
+            >,
+        >,
     >,
 );
 
 impl SocketSend {
-    pub async fn send(&mut self, value: BackendData) -> anyhow::Result<()> {
+    pub async fn send(&self, value: BackendData) -> anyhow::Result<()> {
         Ok(self
             .0
+            .lock()
+            .await
             .send(tokio_tungstenite::tungstenite::Message::Text(
                 serde_json::to_string(&value).context("Couldn't serialize JSON")?,
             ))
@@ -85,39 +122,207 @@ impl SocketSend {
 #[serde(rename_all = "UPPERCASE")]
 #[serde(tag = "dataKind")]
 pub enum BackendData {
-    Statistic(SysData),
+    Statistic(Box<SysData>),
     Process(ProcessList),
     Software(DPSoftwareList),
     Management(HostData),
     Service(ServiceList),
     Global(GlobalData),
     Browser(BrowserList),
+    History(HistoryData),
+    Sessions(SessionList),
+    #[serde(rename = "TOKENS")]
+    ApiTokens(ApiTokenList),
+    Audit(AuditList),
+    Recordings(RecordingList),
+    Alert(Alert),
     Reauth,
 }
 
-#[derive(Serialize, Default)]
+#[derive(Serialize, Default, Clone)]
 pub struct SysData {
     pub cpu: f32,
+    pub cpu_detail: CpuDetail,
     pub ram: UsageData,
     pub swap: UsageData,
+    pub memory: MemoryDetail,
+    pub pressure: PressureData,
     pub disk: UsageData,
+    pub filesystems: Vec<FilesystemData>,
+    pub disk_io: Vec<DiskIoData>,
     pub network: NetData,
+    pub nics: Vec<NicData>,
     pub temp: CPUTemp,
+    pub sensors: SensorData,
 }
 
-#[derive(Serialize, Default)]
+#[derive(Serialize, Default, Clone)]
+pub struct CpuDetail {
+    pub cores: Vec<CoreData>,
+    pub times: CpuTimes,
+    pub load: LoadAvg,
+    // Per second
+    pub ctx_switches: u64,
+    pub interrupts: u64,
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct CoreData {
+    pub usage: f32,
+    pub freq: Option<CpuFreq>,
+}
+
+// In MHz
+#[derive(Serialize, Default, Clone)]
+pub struct CpuFreq {
+    pub current: u32,
+    pub min: u32,
+    pub max: u32,
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct CpuTimes {
+    pub user: f32,
+    pub system: f32,
+    pub iowait: f32,
+    pub steal: f32,
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct LoadAvg {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+}
+
+#[derive(Serialize, Default, Clone)]
 pub struct UsageData {
     pub used: u64,
     pub total: u64,
     pub percent: f32,
 }
 
-#[derive(Serialize, Default, Debug)]
+// In bytes
+#[derive(Serialize, Default, Clone)]
+pub struct MemoryDetail {
+    pub available: u64,
+    pub buffers: u64,
+    pub cached: u64,
+    pub shared: u64,
+    pub dirty: u64,
+    pub swap_cached: u64,
+    pub zswap: Option<ZswapData>,
+    pub zram: Vec<ZramData>,
+    pub huge_pages: HugePages,
+    // Since boot, None on kernels that don't count them
+    pub oom_kills: Option<u64>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ZswapData {
+    pub compressed: u64,
+    pub original: u64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ZramData {
+    pub name: String,
+    pub original: u64,
+    pub compressed: u64,
+    // Including overhead
+    pub used: u64,
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct HugePages {
+    pub total: u64,
+    pub free: u64,
+    // In bytes
+    pub size: u64,
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct PressureData {
+    pub cpu: Option<Pressure>,
+    pub memory: Option<Pressure>,
+    pub io: Option<Pressure>,
+}
+
+// "some" is the share of time that at least one task was stalled, "full" is when all of them were
+#[derive(Serialize, Clone)]
+pub struct Pressure {
+    pub some: PressureAvg,
+    pub full: Option<PressureAvg>,
+}
+
+// Percentages over the last 10, 60 and 300 seconds
+#[derive(Serialize, Clone)]
+pub struct PressureAvg {
+    pub avg10: f32,
+    pub avg60: f32,
+    pub avg300: f32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct FilesystemData {
+    pub device: String,
+    pub mountpoint: String,
+    pub fstype: String,
+    pub usage: UsageData,
+    // Some filesystems (like btrfs and vfat) have no fixed number of inodes, those have 0 total
+    pub inodes: UsageData,
+}
+
+#[derive(Serialize, Clone)]
+pub struct DiskIoData {
+    pub name: String,
+    // Per second
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub read_iops: u64,
+    pub write_iops: u64,
+    // Percent of the time that the disk was busy
+    pub util: f32,
+    // Average milliseconds per request
+    pub wait: f32,
+}
+
+#[derive(Serialize, Default, Debug, Clone)]
 pub struct NetData {
+    // Bytes per second
     pub sent: u64,
     pub received: u64,
 }
 
+#[derive(Serialize, Clone)]
+pub struct NicData {
+    pub name: String,
+    // False for loopback, bridges, VPNs and other virtual interfaces
+    pub physical: bool,
+    // From sysfs, like "up", "down" or "unknown"
+    pub state: String,
+    // In Mbit/s
+    pub speed: Option<u32>,
+    pub duplex: Option<String>,
+    pub mtu: Option<u32>,
+    pub mac: Option<String>,
+    pub addrs: Vec<String>,
+    pub rates: NicRates,
+}
+
+// Per second
+#[derive(Serialize, Default, Clone)]
+pub struct NicRates {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub errors_in: u64,
+    pub errors_out: u64,
+    pub drops_in: u64,
+    pub drops_out: u64,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(untagged)]
 pub enum RequestTypes {
@@ -131,9 +336,35 @@ pub enum RequestTypes {
     Token {
         token: String,
     },
+    History {
+        range: u64,
+    },
+    // Sampling interval in milliseconds for the current page, 0 for the default
+    Interval {
+        interval: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+pub struct HistoryPoint {
+    pub time: u64,
+    pub cpu: f32,
+    pub ram: u64,
+    pub swap: u64,
+    pub disk: u64,
+    // Bytes per second, averaged over the point's time like everything else
+    pub sent: u64,
+    pub received: u64,
+    pub temp: Option<i16>,
 }
 
 #[derive(Serialize)]
+pub struct HistoryData {
+    pub range: u64,
+    pub points: Vec<HistoryPoint>,
+}
+
+#[derive(Serialize, Clone)]
 pub struct ProcessData {
     pub pid: u32,
     pub name: String,
@@ -190,6 +421,73 @@ pub struct ServiceList {
     pub services: Vec<ServiceData>,
 }
 
+#[derive(Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub user: String,
+    pub role: Role,
+    pub addr: String,
+    pub user_agent: String,
+    pub issued: u64,
+    pub expires: u64,
+}
+
+#[derive(Serialize)]
+pub struct SessionList {
+    pub sessions: Vec<SessionInfo>,
+}
+
+#[derive(Serialize)]
+pub struct TerminalInfo {
+    pub id: String,
+    pub name: String,
+    pub user: Option<String>,
+    pub addr: String,
+    pub created: u64,
+    pub clients: usize,
+    // Seconds since the last client disconnected, None while attached
+    pub idle: Option<u64>,
+    pub viewers: Vec<ViewerInfo>,
+}
+
+#[derive(Serialize)]
+pub struct ViewerInfo {
+    pub user: Option<String>,
+    pub addr: String,
+    pub since: u64,
+}
+
+#[derive(Serialize)]
+pub struct TerminalList {
+    pub terminals: Vec<TerminalInfo>,
+}
+
+#[derive(Serialize)]
+pub struct RecordingInfo {
+    pub name: String,
+    pub title: String,
+    pub user: Option<String>,
+    pub addr: String,
+    pub time: u64,
+    // Seconds from the start until the last change
+    pub duration: u64,
+    pub size: u64,
+}
+
+#[derive(Serialize)]
+pub struct RecordingData {
+    pub name: String,
+    // asciicast v2
+    pub data: String,
+}
+
+#[derive(Serialize)]
+pub struct RecordingList {
+    pub recordings: Vec<RecordingInfo>,
+    // Set after opening a recording, for replaying it
+    pub open: Option<RecordingData>,
+}
+
 #[derive(Serialize)]
 pub struct GlobalData {
     pub update: String,
@@ -216,6 +514,11 @@ pub struct BrowserList {
     pub contents: Vec<BrowserData>,
 }
 
+#[derive(Serialize)]
+pub struct ApiError {
+    pub error: String,
+}
+
 #[derive(Serialize)]
 pub struct TokenError {
     pub error: bool,
@@ -247,9 +550,209 @@ pub struct JWTClaims {
     pub exp: u64,
     pub iat: u64,
     pub fingerprint: String,
+    pub user: String,
+    pub role: Role,
+    // Session ID, see sessions.rs
+    pub jti: String,
 }
 
-#[derive(Serialize, Default)]
+// Ordered from least to most access, so roles can be compared
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin,
+}
+
+// Lowest role that's allowed to run a command on a page, "/file" and "/terminal" are the file and terminal sockets
+// Files are admin only, even for reading, since the dashboard runs as root and its own secrets are files too
+pub fn required_role(page: &str, cmd: &str) -> Role {
+    match (page, cmd) {
+        ("/process" | "/service", _) => Role::Operator,
+        _ => Role::Admin,
+    }
+}
+
+// Lowest role that's allowed to view a page at all
+pub fn page_role(page: &str) -> Role {
+    match page {
+        "/sessions" | "/tokens" | "/audit" | "/recordings" | "/browser" => Role::Admin,
+        _ => Role::Viewer,
+    }
+}
+
+// What API tokens can be allowed to do, much narrower than roles
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    #[serde(rename = "stats:read")]
+    StatsRead,
+    // Grants nothing anymore, since reading any file gives away the dashboard's own secrets
+    // Kept so that token files with it can still be read
+    #[serde(rename = "files:read")]
+    FilesRead,
+    #[serde(rename = "processes:write")]
+    ProcessesWrite,
+    #[serde(rename = "services:write")]
+    ServicesWrite,
+    #[serde(rename = "software:write")]
+    SoftwareWrite,
+}
+
+impl Scope {
+    // The ones that new tokens can be given
+    pub const ALL: [Self; 4] = [
+        Self::StatsRead,
+        Self::ProcessesWrite,
+        Self::ServicesWrite,
+        Self::SoftwareWrite,
+    ];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::StatsRead => "stats:read",
+            Self::FilesRead => "files:read",
+            Self::ProcessesWrite => "processes:write",
+            Self::ServicesWrite => "services:write",
+            Self::SoftwareWrite => "software:write",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|x| x.as_str() == scope)
+    }
+}
+
+// Scope needed to view a page, None if API tokens can't view it at all
+fn page_scope(page: &str) -> Option<Scope> {
+    match page {
+        "/" | "/process" | "/service" | "/software" | "/management" => Some(Scope::StatsRead),
+        _ => None,
+    }
+}
+
+// Scope needed to run a command on a page, see required_role
+fn required_scope(page: &str, cmd: &str) -> Option<Scope> {
+    match (page, cmd) {
+        ("/process", _) => Some(Scope::ProcessesWrite),
+        ("/service", _) => Some(Scope::ServicesWrite),
+        ("/software", _) => Some(Scope::SoftwareWrite),
+        _ => None,
+    }
+}
+
+// What a client may do, either as a logged in user or with an API token
+#[derive(Clone, Debug)]
+pub enum Access {
+    Role(Role),
+    Scopes(Vec<Scope>),
+}
+
+impl Access {
+    pub fn can_view(&self, page: &str) -> bool {
+        match self {
+            Self::Role(role) => *role >= page_role(page),
+            Self::Scopes(scopes) => page_scope(page).is_some_and(|x| scopes.contains(&x)),
+        }
+    }
+
+    pub fn can_run(&self, page: &str, cmd: &str) -> bool {
+        match self {
+            Self::Role(role) => *role >= required_role(page, cmd),
+            Self::Scopes(scopes) => required_scope(page, cmd).is_some_and(|x| scopes.contains(&x)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ApiTokenInfo {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created: u64,
+    pub expires: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ApiTokenList {
+    pub tokens: Vec<ApiTokenInfo>,
+    // Only sent right after creating a token, since it can't be shown again
+    pub created: Option<String>,
+}
+
+// One line of the audit log
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: u64,
+    pub user: Option<String>,
+    pub session: Option<String>,
+    // Name of the API token used, if any
+    pub token: Option<String>,
+    pub addr: String,
+    pub action: String,
+    pub args: Vec<String>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Alert {
+    // The same for an alert and its resolution, like "rule:0" or "service:nginx"
+    pub id: String,
+    pub time: u64,
+    pub title: String,
+    pub message: String,
+    // False once the problem is gone
+    pub firing: bool,
+}
+
+#[derive(Serialize)]
+pub struct AuditList {
+    // Newest first
+    pub entries: Vec<AuditEntry>,
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct SensorData {
+    pub temps: Vec<TempSensor>,
+    pub fans: Vec<FanData>,
+    // None when not on a Raspberry Pi
+    pub throttled: Option<ThrottledData>,
+}
+
+// Temperatures are in the configured unit
+#[derive(Serialize, Clone)]
+pub struct TempSensor {
+    // "hwmon" or "thermal_zone"
+    pub source: &'static str,
+    pub name: String,
+    pub label: Option<String>,
+    pub current: f32,
+    pub high: Option<f32>,
+    pub critical: Option<f32>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct FanData {
+    pub name: String,
+    pub label: Option<String>,
+    pub rpm: u32,
+}
+
+// The "_occurred" flags stay set until reboot
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Serialize, Clone)]
+pub struct ThrottledData {
+    pub under_voltage: bool,
+    pub freq_capped: bool,
+    pub throttled: bool,
+    pub soft_temp_limit: bool,
+    pub under_voltage_occurred: bool,
+    pub freq_capped_occurred: bool,
+    pub throttled_occurred: bool,
+    pub soft_temp_limit_occurred: bool,
+}
+
+#[derive(Serialize, Default, Clone)]
 pub struct CPUTemp {
     pub temp: Option<i16>,
 }
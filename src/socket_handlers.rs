@@ -1,50 +1,99 @@
 use anyhow::Context;
 use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
 use std::io::Write;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
 use tokio_tungstenite::tungstenite::Message;
-use tracing::{instrument, Instrument};
+use tracing::instrument;
 
-use crate::{handle_error, page_handlers, shared, systemdata, CONFIG};
+use crate::{
+    alerts, apitokens, audit, handle_error, page_handlers, sessions, shared, systemdata, terminals,
+    CONFIG,
+};
 
-enum TokenState {
+pub enum TokenState {
     InvalidToken,
-    ValidToken,
+    ValidToken(shared::JWTClaims),
+    ApiToken {
+        name: String,
+        scopes: Vec<shared::Scope>,
+    },
     NoFingerprint,
 }
 
 impl TokenState {
-    const fn as_bool(&self) -> bool {
-        if matches!(self, Self::ValidToken) {
+    pub const fn as_bool(&self) -> bool {
+        if matches!(self, Self::ValidToken(_) | Self::ApiToken { .. }) {
             return true;
         }
         false
     }
+
+    pub fn access(&self) -> Option<shared::Access> {
+        match self {
+            Self::ValidToken(claims) => Some(shared::Access::Role(claims.role)),
+            Self::ApiToken { scopes, .. } => Some(shared::Access::Scopes(scopes.clone())),
+            Self::InvalidToken | Self::NoFingerprint => None,
+        }
+    }
+
+    // For closing sockets once the token isn't valid anymore
+    pub fn watch(&self, token: &str) -> Option<sessions::Watch> {
+        match self {
+            Self::ValidToken(claims) => sessions::watch(&claims.jti),
+            Self::ApiToken { .. } => Some(sessions::Watch::api_token(token)),
+            Self::InvalidToken | Self::NoFingerprint => None,
+        }
+    }
+
+    pub fn actor(&self, addr: std::net::IpAddr) -> audit::Actor {
+        let mut actor = audit::Actor::anonymous(addr);
+        match self {
+            Self::ValidToken(claims) => {
+                actor.user = Some(claims.user.clone());
+                actor.session = Some(claims.jti.clone());
+            }
+            Self::ApiToken { name, .. } => actor.token = Some(name.clone()),
+            Self::InvalidToken | Self::NoFingerprint => {}
+        }
+        actor
+    }
 }
 
 #[instrument(level = "debug", skip_all)]
-fn validate_token(token: &str, fingerprint: Option<&str>) -> TokenState {
+pub fn validate_token(token: &str, fingerprint: Option<&str>) -> TokenState {
+    // Meant for scripts, so they don't need a fingerprint cookie
+    if token.starts_with(apitokens::PREFIX) {
+        return apitokens::validate(token).map_or(TokenState::InvalidToken, |(name, scopes)| {
+            TokenState::ApiToken { name, scopes }
+        });
+    }
     let mut validator = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
     validator.set_issuer(&["DietPi Dashboard"]);
     validator.set_required_spec_claims(&["exp", "iat"]);
-    if let Ok(claims) = jsonwebtoken::decode::<shared::JWTClaims>(
+    let Ok(claims) = jsonwebtoken::decode::<shared::JWTClaims>(
         token,
         &jsonwebtoken::DecodingKey::from_secret(CONFIG.secret.as_bytes()),
         &validator,
-    ) {
-        if let Some(fingerprint) = fingerprint {
-            if claims.claims.fingerprint != fingerprint {
-                return TokenState::InvalidToken;
-            }
-        } else {
-            return TokenState::NoFingerprint;
+    ) else {
+        tracing::debug!("Invalid token");
+        return TokenState::InvalidToken;
+    };
+    if let Some(fingerprint) = fingerprint {
+        if claims.claims.fingerprint != fingerprint {
+            return TokenState::InvalidToken;
         }
     } else {
-        tracing::debug!("Invalid token");
+        return TokenState::NoFingerprint;
+    }
+    // Tokens are only as good as the session they belong to
+    if !sessions::is_active(&claims.claims.jti) {
+        tracing::debug!("Session has ended");
         return TokenState::InvalidToken;
     }
-    TokenState::ValidToken
+    TokenState::ValidToken(claims.claims)
 }
 
 #[instrument(skip_all)]
@@ -53,14 +102,33 @@ pub async fn socket_handler(
     socket: tokio_tungstenite::WebSocketStream<hyper::upgrade::Upgraded>,
     fingerprint: Option<String>,
     _token: String,
+    remote_addr: std::net::IpAddr,
 ) {
     let (socket_send, mut socket_recv) = socket.split();
     let (data_send, mut data_recv) = mpsc::channel(1);
+    // Kept up to date with the token, for the audit log
+    let (actor_send, actor) = watch::channel(audit::Actor::anonymous(remote_addr));
+    // Whether the client is logged in, so alerts only go to those that are
+    let (authed_send, authed) = watch::channel(!CONFIG.pass);
     tokio::task::spawn(async move {
         let mut first_message = true;
         let mut req: shared::RequestTypes;
         let mut token = String::new();
-        while let Some(Ok(data)) = socket_recv.next().await {
+        let mut page = String::new();
+        // Everyone has full access without a password
+        let mut access = shared::Access::Role(shared::Role::Admin);
+        let mut session = None;
+        loop {
+            let data = tokio::select! {
+                data = socket_recv.next() => data,
+                () = sessions::ended(&mut session) => {
+                    tracing::info!("Session ended, closing socket");
+                    break;
+                }
+            };
+            let Some(Ok(data)) = data else {
+                break;
+            };
             if data.is_close() {
                 break;
             }
@@ -86,6 +154,7 @@ pub async fn socket_handler(
             }
             if CONFIG.pass {
                 let validation = validate_token(&token, fingerprint.as_deref());
+                authed_send.send_replace(validation.as_bool());
                 if !validation.as_bool() {
                     if !first_message {
                         tracing::debug!("Requesting login");
@@ -104,8 +173,26 @@ pub async fn socket_handler(
                 match validation {
                     TokenState::InvalidToken => continue,
                     TokenState::NoFingerprint => return,
-                    TokenState::ValidToken => {}
+                    TokenState::ValidToken(_) | TokenState::ApiToken { .. } => {
+                        session = validation.watch(&token);
+                        actor_send.send_replace(validation.actor(remote_addr));
+                        if let Some(new_access) = validation.access() {
+                            access = new_access;
+                        }
+                    }
+                }
+            }
+            match &req {
+                shared::RequestTypes::Page { page: new_page } if !access.can_view(new_page) => {
+                    tracing::warn!("{:?} isn't allowed to view {}", access, new_page);
+                    continue;
                 }
+                shared::RequestTypes::Page { page: new_page } => page.clone_from(new_page),
+                shared::RequestTypes::Cmd { cmd, .. } if !access.can_run(&page, cmd) => {
+                    tracing::warn!("{:?} isn't allowed to run {} on {}", access, cmd, page);
+                    continue;
+                }
+                _ => {}
             }
             if let shared::RequestTypes::Page { .. } = req {
                 // Quit out of handler
@@ -125,7 +212,8 @@ pub async fn socket_handler(
             }
         }
     });
-    let mut socket_send = shared::SocketSend(socket_send);
+    let mut socket_send =
+        shared::SocketSend(std::sync::Arc::new(tokio::sync::Mutex::new(socket_send)));
     // Send global message (shown on all pages)
     if socket_send
         .send(shared::BackendData::Global(systemdata::global().await))
@@ -134,24 +222,37 @@ pub async fn socket_handler(
     {
         return;
     }
+    let alert_task = tokio::spawn(forward_alerts(socket_send.clone(), authed));
     while let Some(Some(message)) = data_recv.recv().await {
         if let shared::RequestTypes::Page { page } = message {
             if match page.as_str() {
                 "/" => page_handlers::main_handler(&mut socket_send, &mut data_recv).await,
                 "/process" => {
-                    page_handlers::process_handler(&mut socket_send, &mut data_recv).await
+                    page_handlers::process_handler(&mut socket_send, &mut data_recv, &actor).await
                 }
                 "/software" => {
-                    page_handlers::software_handler(&mut socket_send, &mut data_recv).await
+                    page_handlers::software_handler(&mut socket_send, &mut data_recv, &actor).await
                 }
                 "/management" => {
-                    page_handlers::management_handler(&mut socket_send, &mut data_recv).await
+                    page_handlers::management_handler(&mut socket_send, &mut data_recv, &actor)
+                        .await
                 }
                 "/service" => {
-                    page_handlers::service_handler(&mut socket_send, &mut data_recv).await
+                    page_handlers::service_handler(&mut socket_send, &mut data_recv, &actor).await
                 }
                 "/browser" => {
-                    page_handlers::browser_handler(&mut socket_send, &mut data_recv).await
+                    page_handlers::browser_handler(&mut socket_send, &mut data_recv, &actor).await
+                }
+                "/sessions" => {
+                    page_handlers::sessions_handler(&mut socket_send, &mut data_recv, &actor).await
+                }
+                "/tokens" => {
+                    page_handlers::tokens_handler(&mut socket_send, &mut data_recv, &actor).await
+                }
+                "/audit" => page_handlers::audit_handler(&mut socket_send, &mut data_recv).await,
+                "/recordings" => {
+                    page_handlers::recordings_handler(&mut socket_send, &mut data_recv, &actor)
+                        .await
                 }
                 "/login" => {
                     tracing::debug!("Sending login message");
@@ -170,6 +271,58 @@ pub async fn socket_handler(
             }
         }
     }
+    // Also reached when the reader stops, like when the session ends
+    // The frontend reconnects, and gets asked to log in again
+    alert_task.abort();
+    let _close = socket_send.0.lock().await.send(Message::Close(None)).await;
+}
+
+// Forwards alerts while the client is logged in
+async fn forward_alerts(socket_send: shared::SocketSend, mut authed: watch::Receiver<bool>) {
+    loop {
+        if authed.wait_for(|authed| *authed).await.is_err() {
+            return;
+        }
+        tokio::select! {
+            () = alerts::forward(socket_send.clone()) => return,
+            _ = authed.wait_for(|authed| !*authed) => {}
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct TTYNew {
+    #[serde(default)]
+    name: String,
+    // Size of the client's terminal, so the shell starts out with the right one
+    cols: Option<u16>,
+    rows: Option<u16>,
+}
+
+#[derive(serde::Deserialize)]
+struct TTYAttach {
+    id: String,
+    // Bytes of output the client already has
+    offset: Option<u64>,
+}
+
+#[derive(serde::Deserialize)]
+struct TTYRename {
+    id: String,
+    name: String,
+}
+
+// For closing, sharing and stopping to share
+#[derive(serde::Deserialize)]
+struct TTYId {
+    id: String,
+}
+
+#[derive(serde::Deserialize)]
+struct TTYView {
+    share: String,
+    // Bytes of output the client already has
+    offset: Option<u64>,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -178,108 +331,375 @@ struct TTYSize {
     rows: u16,
 }
 
+// Splits control messages like size{"cols":80,"rows":24} into their kind and JSON
+fn tty_control(data_str: &str) -> Option<(&str, &str)> {
+    let (kind, json) = data_str.split_at(data_str.find('{')?);
+    matches!(
+        kind,
+        "size" | "new" | "attach" | "rename" | "close" | "share" | "unshare" | "list" | "view"
+    )
+    .then_some((kind, json))
+}
+
+fn tty_parse<T: serde::de::DeserializeOwned>(kind: &str, json: &str) -> Option<T> {
+    Some(handle_error!(
+        serde_json::from_str(json)
+            .with_context(|| format!("Couldn't deserialize terminal {kind} message from {json}")),
+        return None
+    ))
+}
+
+// The terminals that one socket has open, like tabs
+struct TermTabs {
+    actor: audit::Actor,
+    tabs: HashMap<String, terminals::Attached>,
+    // Output is only sent for the terminal that's shown
+    active: Option<(String, broadcast::Receiver<Vec<u8>>)>,
+}
+
+impl TermTabs {
+    fn list(&self) -> Message {
+        let terminals = terminals::list()
+            .into_iter()
+            .filter(|x| x.user == self.actor.user)
+            .collect::<Vec<_>>();
+        Message::text(format!(
+            "terminals{}",
+            serde_json::json!({ "terminals": terminals })
+        ))
+    }
+
+    fn closed(&mut self, id: &str) -> Vec<Message> {
+        self.tabs.remove(id);
+        if self.active.as_ref().is_some_and(|(active, _)| active == id) {
+            self.active = None;
+        }
+        vec![
+            Message::text(format!("closed{}", serde_json::json!({ "id": id }))),
+            self.list(),
+        ]
+    }
+
+    // Everything sent after the "attached" message is output of that terminal
+    fn activate(&mut self, id: &str, offset: Option<u64>) -> Vec<Message> {
+        let Some(output) = self.tabs.get(id).and_then(|x| x.output(offset)) else {
+            return self.closed(id);
+        };
+        self.active = Some((id.to_string(), output.receiver));
+        let mut messages = vec![Message::text(format!(
+            "attached{}",
+            serde_json::json!({ "id": id, "offset": output.offset })
+        ))];
+        if !output.replay.is_empty() {
+            messages.push(Message::binary(output.replay));
+        }
+        messages
+    }
+
+    fn spawn(&mut self, name: &str, size: Option<(u16, u16)>) -> Vec<Message> {
+        let attached = handle_error!(terminals::spawn(&self.actor, name, size), return Vec::new());
+        let id = attached.id.clone();
+        self.tabs.insert(id.clone(), attached);
+        let mut messages = self.activate(&id, None);
+        messages.push(self.list());
+        messages
+    }
+
+    async fn input(&mut self, input: terminals::Input) -> Vec<Message> {
+        // Clients that don't know about tabs just start using the terminal
+        let messages = if self.active.is_none() {
+            self.spawn("Terminal", None)
+        } else {
+            Vec::new()
+        };
+        if let Some(tab) = self.active.as_ref().and_then(|(id, _)| self.tabs.get(id)) {
+            // Only fails once the terminal has closed, which the output notices
+            let _ = tab.input.send(input).await;
+        }
+        messages
+    }
+
+    // Typed input only comes as binary, so text that happens to look like a control message never counts as one
+    async fn message(&mut self, data: Message) -> Vec<Message> {
+        let data_str = match data {
+            Message::Text(data_str) => data_str,
+            Message::Binary(data_bin) => return self.input(terminals::Input::Data(data_bin)).await,
+            _ => return Vec::new(),
+        };
+        let Some((kind, json)) = tty_control(&data_str) else {
+            tracing::warn!("Unknown terminal control message {}", data_str);
+            return Vec::new();
+        };
+        match kind {
+            "size" => {
+                let Some(json) = tty_parse::<TTYSize>(kind, json) else {
+                    return Vec::new();
+                };
+                tracing::debug!("Got size message {:?}", json);
+                self.input(terminals::Input::Resize {
+                    cols: json.cols,
+                    rows: json.rows,
+                })
+                .await
+            }
+            "new" => tty_parse::<TTYNew>(kind, json)
+                .map_or_else(Vec::new, |x| self.spawn(&x.name, x.cols.zip(x.rows))),
+            "attach" => {
+                let Some(json) = tty_parse::<TTYAttach>(kind, json) else {
+                    return Vec::new();
+                };
+                if !self.tabs.contains_key(&json.id) {
+                    let attached = handle_error!(
+                        terminals::attach(&json.id, &self.actor),
+                        return self.closed(&json.id)
+                    );
+                    self.tabs.insert(json.id.clone(), attached);
+                }
+                self.activate(&json.id, json.offset)
+            }
+            "rename" => {
+                let Some(json) = tty_parse::<TTYRename>(kind, json) else {
+                    return Vec::new();
+                };
+                if self.tabs.contains_key(&json.id) {
+                    terminals::rename(&json.id, &json.name);
+                }
+                vec![self.list()]
+            }
+            "close" => {
+                let Some(json) = tty_parse::<TTYId>(kind, json) else {
+                    return Vec::new();
+                };
+                if self.tabs.contains_key(&json.id) {
+                    terminals::kill(&json.id);
+                    handle_error!(audit::record(
+                        &self.actor,
+                        "terminal.close",
+                        &[&json.id],
+                        Ok(())
+                    ));
+                }
+                self.closed(&json.id)
+            }
+            "share" => {
+                let Some(json) = tty_parse::<TTYId>(kind, json) else {
+                    return Vec::new();
+                };
+                if !self.tabs.contains_key(&json.id) {
+                    return Vec::new();
+                }
+                let (share, expires) =
+                    handle_error!(terminals::share(&json.id, &self.actor), return Vec::new());
+                vec![Message::text(format!(
+                    "shared{}",
+                    serde_json::json!({ "id": json.id, "share": share, "expires": expires })
+                ))]
+            }
+            "unshare" => {
+                let Some(json) = tty_parse::<TTYId>(kind, json) else {
+                    return Vec::new();
+                };
+                if self.tabs.contains_key(&json.id) {
+                    terminals::unshare(&json.id);
+                    handle_error!(audit::record(
+                        &self.actor,
+                        "terminal.unshare",
+                        &[&json.id],
+                        Ok(())
+                    ));
+                }
+                Vec::new()
+            }
+            _ => vec![self.list()],
+        }
+    }
+}
+
 #[instrument(skip_all)]
 pub async fn term_handler(
     socket: tokio_tungstenite::WebSocketStream<hyper::upgrade::Upgraded>,
     fingerprint: Option<String>,
     token: String,
+    remote_addr: std::net::IpAddr,
 ) {
     let (mut socket_send, mut socket_recv) = socket.split();
 
-    if crate::CONFIG.pass && !validate_token(&token, fingerprint.as_deref()).as_bool() {
-        return;
+    let (mut session, actor) = if crate::CONFIG.pass {
+        let validation = validate_token(&token, fingerprint.as_deref());
+        let Some(access) = validation.access() else {
+            let _close = socket_send
+                .send(sessions::close_message("Invalid token"))
+                .await;
+            return;
+        };
+        if !access.can_run("/terminal", "") {
+            tracing::warn!("Not allowed to open terminal");
+            // Tells the client not to keep reconnecting
+            let _close = socket_send
+                .send(close_message(CloseCode::Policy, "Not allowed"))
+                .await;
+            return;
+        }
+        (validation.watch(&token), validation.actor(remote_addr))
+    } else {
+        (None, audit::Actor::anonymous(remote_addr))
+    };
+
+    let mut tabs = TermTabs {
+        actor,
+        tabs: HashMap::new(),
+        active: None,
+    };
+    let mut changes = terminals::changes();
+    loop {
+        let messages = tokio::select! {
+            data = socket_recv.next() => {
+                let Some(Ok(data)) = data else {
+                    // The terminals keep running, for the client to reconnect to
+                    tracing::debug!("Socket closed, breaking");
+                    break;
+                };
+                tabs.message(data).await
+            }
+            data = async {
+                match &mut tabs.active {
+                    Some((_, output)) => output.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => match data {
+                Ok(data) => vec![Message::binary(data)],
+                // The client reconnects, and gets the missed output replayed
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    tracing::debug!("Client can't keep up with terminal output, closing");
+                    let _close = socket_send
+                        .send(close_message(CloseCode::Again, "Too much output"))
+                        .await;
+                    break;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    tracing::debug!("Terminal closed");
+                    match tabs.active.take() {
+                        Some((id, _)) => tabs.closed(&id),
+                        None => Vec::new(),
+                    }
+                }
+            },
+            // Like when someone starts watching
+            _ = changes.changed() => vec![tabs.list()],
+            () = sessions::ended(&mut session) => {
+                tracing::info!("Session ended, detaching from terminals");
+                let _close = socket_send.send(sessions::close_message("Session ended")).await;
+                break;
+            }
+        };
+        for message in messages {
+            if socket_send.send(message).await.is_err() {
+                tracing::debug!("Socket closed, breaking");
+                return;
+            }
+        }
     }
 
-    let pty = handle_error!(
-        pty_process::Pty::new().context("Couldn't spawn pty"),
-        return
-    );
+    tracing::info!("Detached from terminals");
+}
 
-    let mut cmd = pty_process::Command::new("/bin/login");
-    cmd.env("TERM", "xterm");
+// Watches a terminal that has been shared, anything sent by the client is ignored
+#[instrument(skip_all)]
+pub async fn term_view_handler(
+    socket: tokio_tungstenite::WebSocketStream<hyper::upgrade::Upgraded>,
+    fingerprint: Option<String>,
+    token: String,
+    remote_addr: std::net::IpAddr,
+) {
+    let (mut socket_send, mut socket_recv) = socket.split();
 
-    if crate::CONFIG.terminal_user != "manual" {
-        cmd.args(["-f", &crate::CONFIG.terminal_user]);
-    }
+    // Any login will do, the share token is what allows watching
+    let (mut session, actor) = if crate::CONFIG.pass {
+        let validation = validate_token(&token, fingerprint.as_deref());
+        if validation.access().is_none() {
+            let _close = socket_send
+                .send(sessions::close_message("Invalid token"))
+                .await;
+            return;
+        }
+        (validation.watch(&token), validation.actor(remote_addr))
+    } else {
+        (None, audit::Actor::anonymous(remote_addr))
+    };
 
-    let pts = handle_error!(pty.pts().context("Couldn't spawn pts"), return);
+    let Some(Ok(Message::Text(data_str))) = socket_recv.next().await else {
+        return;
+    };
+    let Some(request) = tty_control(&data_str)
+        .filter(|(kind, _)| *kind == "view")
+        .and_then(|(kind, json)| tty_parse::<TTYView>(kind, json))
+    else {
+        return;
+    };
+    let mut viewing = handle_error!(terminals::view(&request.share, &actor), {
+        let _close = socket_send
+            .send(close_message(CloseCode::Policy, "Invalid or expired link"))
+            .await;
+        return;
+    });
+    let Some(mut output) = viewing.output(request.offset) else {
+        let _close = socket_send
+            .send(close_message(CloseCode::Normal, "Terminal closed"))
+            .await;
+        return;
+    };
 
-    let mut child = handle_error!(
-        cmd.spawn(&pts).context("Couldn't spawn command onto pts"),
-        return
+    let viewing_msg = format!(
+        "viewing{}",
+        serde_json::json!({ "name": viewing.name, "offset": output.offset })
     );
+    if socket_send.send(Message::text(viewing_msg)).await.is_err()
+        || (!output.replay.is_empty()
+            && socket_send
+                .send(Message::binary(output.replay))
+                .await
+                .is_err())
+    {
+        return;
+    }
 
-    let (mut pty_read, mut pty_write) = pty.into_split();
-
-    tokio::join!(
-        async {
-            loop {
-                let mut data = [0; 256];
-                let read_res = pty_read.read(&mut data).await;
-                if let Ok(num_read) = read_res {
-                    if socket_send
-                        .send(Message::binary(&data[..num_read]))
-                        .await
-                        .is_err()
-                    {
-                        tracing::debug!("Socket closed, breaking");
-                        break;
-                    }
-                } else {
-                    tracing::debug!("Terminal closed, breaking");
-                    break;
+    loop {
+        let message = tokio::select! {
+            data = output.receiver.recv() => match data {
+                Ok(data) => Message::binary(data),
+                // The client reconnects, and gets the missed output replayed
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    close_message(CloseCode::Again, "Too much output")
                 }
-            }
-        }
-        .instrument(tracing::debug_span!("term_reader")),
-        async {
-            loop {
-                if let Some(Ok(data)) = socket_recv.next().await {
-                    match data {
-                        Message::Text(data_str) => {
-                            if data_str.get(..4) == Some("size") {
-                                let json: TTYSize = handle_error!(
-                                    serde_json::from_str(&data_str[4..]).with_context(|| format!(
-                                        "Couldn't deserialize pty size from {}",
-                                        &data_str
-                                    )),
-                                    continue
-                                );
-                                tracing::debug!("Got size message {:?}", json);
-                                handle_error!(pty_write
-                                    .resize(pty_process::Size::new(json.rows, json.cols))
-                                    .context("Couldn't resize pty"));
-                            } else if pty_write.write_all(data_str.as_bytes()).await.is_err() {
-                                tracing::debug!("Terminal closed, breaking");
-                                break;
-                            }
-                        }
-                        Message::Binary(data_bin) => {
-                            if pty_write.write_all(&data_bin).await.is_err() {
-                                tracing::debug!("Terminal closed, breaking");
-                                break;
-                            }
-                        }
-                        _ => {}
-                    }
-                } else {
-                    tracing::debug!("Exiting terminal");
-                    // Stop bash by writing "exit", since it won't respond to a SIGTERM
-                    let _write = pty_write.write_all(b"exit\n");
-                    break;
+                Err(broadcast::error::RecvError::Closed) => {
+                    close_message(CloseCode::Normal, "Terminal closed")
+                }
+            },
+            data = socket_recv.next() => {
+                // Input and size changes are dropped
+                if matches!(data, Some(Ok(_))) {
+                    continue;
                 }
+                tracing::debug!("Socket closed, breaking");
+                break;
             }
+            _ = viewing.unshared.changed() => close_message(CloseCode::Normal, "Stopped sharing"),
+            () = sessions::ended(&mut session) => sessions::close_message("Session ended"),
+        };
+        let is_close = message.is_close();
+        if socket_send.send(message).await.is_err() || is_close {
+            break;
         }
-        .instrument(tracing::debug_span!("term_writer"))
-    );
+    }
 
-    // Reap PID, unwrap is safe because all references will have been dropped
-    handle_error!(
-        child.wait().await.context("Couldn't close terminal"),
-        return
-    );
+    tracing::info!("Stopped watching terminal");
+}
 
-    tracing::info!("Closed terminal");
+fn close_message(code: CloseCode, reason: &str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: reason.to_string().into(),
+    }))
 }
 
 #[instrument(level = "debug", skip_all)]
@@ -423,11 +843,45 @@ pub async fn file_handler(
     socket: tokio_tungstenite::WebSocketStream<hyper::upgrade::Upgraded>,
     fingerprint: Option<String>,
     token: String,
+    remote_addr: std::net::IpAddr,
 ) {
     let (mut socket_send, mut socket_recv) = socket.split();
     let mut req: shared::FileRequest;
 
-    'outer: while let Some(Ok(data)) = socket_recv.next().await {
+    // Checked once, after that the socket lives as long as the session
+    let (access, mut session, actor) = if CONFIG.pass {
+        let validation = validate_token(&token, fingerprint.as_deref());
+        let Some(access) = validation.access() else {
+            let _close = socket_send
+                .send(sessions::close_message("Invalid token"))
+                .await;
+            return;
+        };
+        (
+            access,
+            validation.watch(&token),
+            validation.actor(remote_addr),
+        )
+    } else {
+        (
+            shared::Access::Role(shared::Role::Admin),
+            None,
+            audit::Actor::anonymous(remote_addr),
+        )
+    };
+    let mut session_ended = false;
+
+    'outer: loop {
+        let data = tokio::select! {
+            data = socket_recv.next() => data,
+            () = sessions::ended(&mut session) => {
+                session_ended = true;
+                break;
+            }
+        };
+        let Some(Ok(data)) = data else {
+            break;
+        };
         if data.is_close() {
             break;
         }
@@ -436,13 +890,20 @@ pub async fn file_handler(
 
         tracing::debug!("Got file request {:?}", req);
 
-        if CONFIG.pass && !validate_token(&token, fingerprint.as_deref()).as_bool() {
-            return;
+        if !access.can_run("/file", &req.cmd) {
+            tracing::warn!("Not allowed to run file command {}", &req.cmd);
+            continue;
         }
 
         loop {
             tokio::select! {
                 result = file_handler_helper(&req) => {
+                    let result = match req.cmd.as_str() {
+                        "save" => audit::record(&actor, "file.save", &[&req.path], result),
+                        // Uploads are recorded once they're done
+                        "up" if result.is_err() => audit::record(&actor, "file.up", &[&req.path], result),
+                        _ => result,
+                    };
                     match handle_error!(result, continue) {
                         Some(FileHandlerHelperReturns::String(file)) => {
                             if socket_send.send(Message::text(file)).await.is_err() {
@@ -467,21 +928,43 @@ pub async fn file_handler(
                             }
                         }
                         Some(FileHandlerHelperReturns::StreamUpload(size, mut file)) => {
+                            let mut upload = Ok(());
                             while let Some(Ok(Message::Binary(msg))) = (&mut socket_recv).take(size).next().await {
-                                handle_error!(file.write_all(&msg).await.with_context(|| {
+                                upload = file.write_all(&msg).await.with_context(|| {
                                     format!("Couldn't write to file {}, stopping upload", &req.path)
-                                }), continue 'outer);
+                                });
+                                if upload.is_err() {
+                                    break;
+                                }
                             }
+                            handle_error!(audit::record(&actor, "file.up", &[&req.path], upload), continue 'outer);
                         }
                         None => {}
                     }
                     break;
                 },
                 recv = socket_recv.next() => match recv {
-                    Some(Ok(req_tmp)) => req = handle_error!(get_file_req(&req_tmp), continue 'outer),
+                    Some(Ok(req_tmp)) => {
+                        req = handle_error!(get_file_req(&req_tmp), continue 'outer);
+                        if !access.can_run("/file", &req.cmd) {
+                            tracing::warn!("Not allowed to run file command {}", &req.cmd);
+                            continue 'outer;
+                        }
+                    }
                     _ => break 'outer,
                 },
+                () = sessions::ended(&mut session) => {
+                    session_ended = true;
+                    break 'outer;
+                }
             }
         }
     }
+
+    if session_ended {
+        tracing::info!("Session ended, closing file socket");
+        let _close = socket_send
+            .send(sessions::close_message("Session ended"))
+            .await;
+    }
 }
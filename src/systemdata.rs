@@ -1,7 +1,9 @@
 use anyhow::Context;
+use psutil::cpu::os::linux::CpuTimesPercentExt;
 use psutil::{cpu, disk, host, memory, network, process, sensors};
+use std::collections::HashMap;
 use std::str::from_utf8;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::fs;
 use tokio::process::Command;
 use tokio::time::sleep;
@@ -22,6 +24,147 @@ pub fn cpu(collector: &mut cpu::CpuPercentCollector) -> anyhow::Result<f32> {
     ))
 }
 
+// Counters in /proc/stat only ever go up, so rates need the previous values
+struct StatCounters {
+    ctxt: u64,
+    intr: u64,
+    // IDs of the online CPUs, in the order that per-CPU times are in, which skips offline ones
+    cpus: Vec<usize>,
+}
+
+fn stat_counters() -> anyhow::Result<StatCounters> {
+    let stat = std::fs::read_to_string("/proc/stat").context("Couldn't read /proc/stat")?;
+    let counter = |name: &str| {
+        stat.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            // The "intr" line has a total first, followed by every single interrupt
+            .and_then(|x| x.split_whitespace().next()?.parse::<u64>().ok())
+            .with_context(|| format!("Couldn't get {name} from /proc/stat"))
+    };
+    Ok(StatCounters {
+        ctxt: counter("ctxt")?,
+        intr: counter("intr")?,
+        cpus: stat
+            .lines()
+            .filter_map(|line| {
+                line.split_whitespace()
+                    .next()?
+                    .strip_prefix("cpu")?
+                    .parse()
+                    .ok()
+            })
+            .collect(),
+    })
+}
+
+pub struct CpuCollector {
+    times: cpu::CpuTimesPercentCollector,
+    counters: StatCounters,
+    last: Instant,
+}
+
+impl CpuCollector {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            times: cpu::CpuTimesPercentCollector::new()
+                .context("Couldn't init CPU times collector")?,
+            // Same as network_start(), the first rates are 0 instead of the total since boot
+            counters: stat_counters().unwrap_or_else(|err| {
+                tracing::debug!("Couldn't get original /proc/stat counters: {err:#}");
+                StatCounters {
+                    ctxt: u64::MAX,
+                    intr: u64::MAX,
+                    cpus: Vec::new(),
+                }
+            }),
+            last: Instant::now(),
+        })
+    }
+}
+
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn per_second(count: u64, elapsed: Duration) -> u64 {
+    if elapsed.is_zero() {
+        return 0;
+    }
+    (count as f64 / elapsed.as_secs_f64()).round() as u64
+}
+
+// In MHz, None without cpufreq (like in most VMs and containers)
+fn cpu_freq(cpu: usize) -> Option<shared::CpuFreq> {
+    let read = |file: &str| {
+        std::fs::read_to_string(format!("/sys/devices/system/cpu/cpu{cpu}/cpufreq/{file}"))
+            .ok()?
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .map(|khz| khz / 1000)
+    };
+    Some(shared::CpuFreq {
+        current: read("scaling_cur_freq")?,
+        min: read("cpuinfo_min_freq")?,
+        max: read("cpuinfo_max_freq")?,
+    })
+}
+
+// Returns the total usage, which is the same as cpu() gives, along with everything else
+#[instrument(skip_all)]
+pub fn cpu_detail(collector: &mut CpuCollector) -> anyhow::Result<(f32, shared::CpuDetail)> {
+    let total = collector
+        .times
+        .cpu_times_percent()
+        .context("Couldn't get CPU times")?;
+    let counters = stat_counters()?;
+    let cores = collector
+        .times
+        .cpu_times_percent_percpu()
+        .context("Couldn't get per-core CPU times")?
+        .iter()
+        .enumerate()
+        .map(|(core, times)| shared::CoreData {
+            usage: round_percent(times.busy()),
+            freq: counters.cpus.get(core).copied().and_then(cpu_freq),
+        })
+        .collect();
+    let load = host::loadavg().context("Couldn't get load averages")?;
+
+    let elapsed = collector.last.elapsed();
+    collector.last = Instant::now();
+    let ctx_switches = per_second(
+        counters.ctxt.saturating_sub(collector.counters.ctxt),
+        elapsed,
+    );
+    let interrupts = per_second(
+        counters.intr.saturating_sub(collector.counters.intr),
+        elapsed,
+    );
+    collector.counters = counters;
+
+    Ok((
+        round_percent(total.busy()),
+        shared::CpuDetail {
+            cores,
+            times: shared::CpuTimes {
+                user: round_percent(total.user()),
+                system: round_percent(total.system()),
+                iowait: round_percent(total.iowait()),
+                steal: round_percent(total.steal().unwrap_or_default()),
+            },
+            load: shared::LoadAvg {
+                one: load.one,
+                five: load.five,
+                fifteen: load.fifteen,
+            },
+            ctx_switches,
+            interrupts,
+        },
+    ))
+}
+
 #[instrument]
 pub fn ram() -> anyhow::Result<shared::UsageData> {
     let ram = memory::virtual_memory().context("Couldn't get memory data")?;
@@ -55,28 +198,427 @@ pub fn disk() -> anyhow::Result<shared::UsageData> {
     })
 }
 
-#[instrument(skip(collector))]
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+fn percent(used: u64, total: u64) -> f32 {
+    if total == 0 {
+        return 0.0;
+    }
+    round_percent((used as f64 / total as f64 * 100.0) as f32)
+}
+
+// statvfs() on these can block for minutes while the server is unreachable
+const NETWORK_FILESYSTEMS: &[&str] = &[
+    "9p",
+    "afs",
+    "ceph",
+    "cifs",
+    "fuse.glusterfs",
+    "fuse.rclone",
+    "fuse.sshfs",
+    "glusterfs",
+    "lustre",
+    "ncpfs",
+    "nfs",
+    "nfs4",
+    "smb3",
+    "smbfs",
+    "sshfs",
+];
+
+fn filesystem_shown(mountpoint: &str, fstype: &str) -> bool {
+    let config = &shared::CONFIG;
+    if config.disk_exclude.iter().any(|x| x == mountpoint) {
+        return false;
+    }
+    if config.disk_include.is_empty() {
+        // Network filesystems need to be listed in disk_include to be shown
+        !config.disk_exclude_types.iter().any(|x| x == fstype)
+            && !NETWORK_FILESYSTEMS.contains(&fstype)
+    } else {
+        config.disk_include.iter().any(|x| x == mountpoint)
+    }
+}
+
+#[instrument]
+pub fn filesystems() -> anyhow::Result<Vec<shared::FilesystemData>> {
+    let partitions = disk::partitions().context("Couldn't get list of mounted filesystems")?;
+    let mut devices = Vec::new();
+    let mut filesystems = Vec::new();
+    for partition in partitions {
+        let mountpoint = partition.mountpoint().to_string_lossy().to_string();
+        let fstype = partition.filesystem().as_str();
+        if !filesystem_shown(&mountpoint, fstype) {
+            continue;
+        }
+        // Bind mounts show the same device again, the first mount is usually the "real" one
+        let device = partition.device();
+        if device.starts_with('/') {
+            if devices.iter().any(|x| x == device) {
+                continue;
+            }
+            devices.push(device.to_string());
+        }
+        // Mounts can go away (or be unreachable) at any time, so only skip that one
+        let stat = crate::handle_error!(
+            nix::sys::statvfs::statvfs(partition.mountpoint())
+                .with_context(|| format!("Couldn't get usage of filesystem {mountpoint}")),
+            continue
+        );
+
+        // Same as psutil's disk_usage(), the percent leaves out space reserved for root
+        // The casts are needed since these are 32-bit on 32-bit ARM
+        let fragment_size = stat.fragment_size() as u64;
+        let total = stat.blocks() as u64 * fragment_size;
+        let used = total - stat.blocks_free() as u64 * fragment_size;
+        let available = stat.blocks_available() as u64 * fragment_size;
+        let inodes_total = stat.files() as u64;
+        let inodes_used = inodes_total - stat.files_free() as u64;
+
+        filesystems.push(shared::FilesystemData {
+            device: device.to_string(),
+            mountpoint,
+            fstype: fstype.to_string(),
+            usage: shared::UsageData {
+                used,
+                total,
+                percent: percent(used, used + available),
+            },
+            inodes: shared::UsageData {
+                used: inodes_used,
+                total: inodes_total,
+                percent: percent(inodes_used, inodes_total),
+            },
+        });
+    }
+    Ok(filesystems)
+}
+
+// Counters of each interface from the last call to network(), since psutil only gives totals since boot
+pub struct NetPrev {
+    nics: HashMap<String, network::NetIoCounters>,
+    time: Instant,
+}
+
+// None without PSI support in the kernel (it needs to be built with CONFIG_PSI)
+fn pressure_file(resource: &str) -> Option<shared::Pressure> {
+    let contents = std::fs::read_to_string(format!("/proc/pressure/{resource}")).ok()?;
+    // Lines look like "some avg10=0.00 avg60=0.00 avg300=0.00 total=0"
+    let line = |kind: &str| {
+        let fields = contents
+            .lines()
+            .find_map(|x| x.strip_prefix(kind)?.strip_prefix(' '))?;
+        let avg = |name: &str| {
+            fields
+                .split_whitespace()
+                .find_map(|x| x.strip_prefix(name)?.strip_prefix('='))?
+                .parse::<f32>()
+                .ok()
+        };
+        Some(shared::PressureAvg {
+            avg10: avg("avg10")?,
+            avg60: avg("avg60")?,
+            avg300: avg("avg300")?,
+        })
+    };
+    Some(shared::Pressure {
+        some: line("some")?,
+        // Not there for the CPU before Linux 5.13
+        full: line("full"),
+    })
+}
+
+#[instrument]
+pub fn pressure() -> shared::PressureData {
+    shared::PressureData {
+        cpu: pressure_file("cpu"),
+        memory: pressure_file("memory"),
+        io: pressure_file("io"),
+    }
+}
+
+// Zram devices are compressed swap in RAM, which DietPi can set up instead of a swap file
+fn zram() -> Vec<shared::ZramData> {
+    sorted_entries("/sys/block", "zram")
+        .into_iter()
+        .filter_map(|dir| {
+            // "orig_data_size compr_data_size mem_used_total ..."
+            let stat = read_value(&dir.join("mm_stat"))?;
+            let mut fields = stat
+                .split_whitespace()
+                .map(|x| x.parse::<u64>().unwrap_or_default());
+            Some(shared::ZramData {
+                name: dir.file_name()?.to_string_lossy().to_string(),
+                original: fields.next()?,
+                compressed: fields.next()?,
+                used: fields.next()?,
+            })
+        })
+        .collect()
+}
+
+#[instrument]
+pub fn memory_detail() -> anyhow::Result<shared::MemoryDetail> {
+    let meminfo =
+        std::fs::read_to_string("/proc/meminfo").context("Couldn't read /proc/meminfo")?;
+    // Most values are in kB, huge page counts don't have a unit
+    let field = |name: &str| {
+        meminfo.lines().find_map(|line| {
+            let mut parts = line
+                .strip_prefix(name)?
+                .strip_prefix(':')?
+                .split_whitespace();
+            let value = parts.next()?;
+            let unit = parts.next().unwrap_or_default();
+            let value = value.parse::<u64>().ok()?;
+            Some(if unit == "kB" { value * 1024 } else { value })
+        })
+    };
+    let vmstat = std::fs::read_to_string("/proc/vmstat").unwrap_or_default();
+
+    Ok(shared::MemoryDetail {
+        available: field("MemAvailable").unwrap_or_default(),
+        buffers: field("Buffers").unwrap_or_default(),
+        cached: field("Cached").unwrap_or_default(),
+        shared: field("Shmem").unwrap_or_default(),
+        dirty: field("Dirty").unwrap_or_default(),
+        swap_cached: field("SwapCached").unwrap_or_default(),
+        // Not there without zswap support in the kernel
+        zswap: field("Zswap")
+            .zip(field("Zswapped"))
+            .map(|(compressed, original)| shared::ZswapData {
+                compressed,
+                original,
+            }),
+        zram: zram(),
+        huge_pages: shared::HugePages {
+            total: field("HugePages_Total").unwrap_or_default(),
+            free: field("HugePages_Free").unwrap_or_default(),
+            size: field("Hugepagesize").unwrap_or_default(),
+        },
+        oom_kills: vmstat
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill ")?.parse::<u64>().ok()),
+    })
+}
+
+// Get a baseline for network(), so that the first measurement isn't the total since boot
+pub fn network_start(collector: &mut network::NetIoCountersCollector) -> NetPrev {
+    NetPrev {
+        nics: collector.net_io_counters_pernic().unwrap_or_else(|_err| {
+            tracing::debug!("Couldn't get original network counter data, starting without it");
+            HashMap::new()
+        }),
+        time: Instant::now(),
+    }
+}
+
+fn nic_file(nic: &str, file: &str) -> Option<String> {
+    std::fs::read_to_string(format!("/sys/class/net/{nic}/{file}"))
+        .ok()
+        .map(|x| x.trim().to_string())
+}
+
+// Loopback, bridges, veths, VPNs and the like all live under /sys/devices/virtual
+fn nic_physical(nic: &str) -> bool {
+    std::fs::canonicalize(format!("/sys/class/net/{nic}"))
+        .is_ok_and(|path| !path.starts_with("/sys/devices/virtual"))
+}
+
+#[instrument(skip_all)]
 pub fn network(
     collector: &mut network::NetIoCountersCollector,
-    prev_data: &mut shared::NetData,
-) -> anyhow::Result<shared::NetData> {
-    let network = collector
-        .net_io_counters()
+    prev_data: &mut NetPrev,
+) -> anyhow::Result<(shared::NetData, Vec<shared::NicData>)> {
+    let counters = collector
+        .net_io_counters_pernic()
         .context("Couldn't get network data")?;
-    let recv = network.bytes_recv();
-    let sent = network.bytes_sent();
+    let elapsed = prev_data.time.elapsed();
+    // Not an error, as there could just be no addresses
+    let addrs = if_addrs::get_if_addrs().unwrap_or_default();
 
-    let data = shared::NetData {
-        received: recv.saturating_sub(prev_data.received),
-        sent: sent.saturating_sub(prev_data.sent),
+    // Bytes since the last call, turned into bytes per second at the end
+    let mut sent = 0;
+    let mut received = 0;
+    let mut nics = Vec::with_capacity(counters.len());
+    for (name, current) in &counters {
+        let physical = nic_physical(name);
+        // New interfaces are measured starting with the next call
+        let rates = prev_data
+            .nics
+            .get(name)
+            .map_or_else(shared::NicRates::default, |prev| {
+                let rate =
+                    |current: u64, prev: u64| per_second(current.saturating_sub(prev), elapsed);
+                if physical || shared::CONFIG.network_total_include.contains(name) {
+                    sent += current.bytes_sent().saturating_sub(prev.bytes_sent());
+                    received += current.bytes_recv().saturating_sub(prev.bytes_recv());
+                }
+                shared::NicRates {
+                    bytes_sent: rate(current.bytes_sent(), prev.bytes_sent()),
+                    bytes_received: rate(current.bytes_recv(), prev.bytes_recv()),
+                    packets_sent: rate(current.packets_sent(), prev.packets_sent()),
+                    packets_received: rate(current.packets_recv(), prev.packets_recv()),
+                    errors_in: rate(current.err_in(), prev.err_in()),
+                    errors_out: rate(current.err_out(), prev.err_out()),
+                    drops_in: rate(current.drop_in(), prev.drop_in()),
+                    drops_out: rate(current.drop_out(), prev.drop_out()),
+                }
+            });
+        nics.push(shared::NicData {
+            name: name.clone(),
+            physical,
+            state: nic_file(name, "operstate").unwrap_or_else(|| "unknown".to_string()),
+            // Negative or unreadable while the link is down
+            speed: nic_file(name, "speed").and_then(|x| x.parse::<u32>().ok()),
+            duplex: nic_file(name, "duplex").filter(|x| x != "unknown"),
+            mtu: nic_file(name, "mtu").and_then(|x| x.parse::<u32>().ok()),
+            mac: nic_file(name, "address").filter(|x| !x.is_empty()),
+            addrs: addrs
+                .iter()
+                .filter(|x| x.name == *name)
+                .map(|x| x.ip().to_string())
+                .collect(),
+            rates,
+        });
+    }
+    nics.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    // Samples aren't always the same time apart, so a rate is what stays comparable
+    let total = shared::NetData {
+        sent: per_second(sent, elapsed),
+        received: per_second(received, elapsed),
     };
 
-    *prev_data = shared::NetData {
-        received: recv,
-        sent,
+    *prev_data = NetPrev {
+        nics: counters,
+        time: Instant::now(),
     };
 
-    Ok(data)
+    Ok((total, nics))
+}
+
+// Counters of one disk from /proc/diskstats
+#[derive(Clone, Copy)]
+struct DiskCounters {
+    reads: u64,
+    sectors_read: u64,
+    ms_reading: u64,
+    writes: u64,
+    sectors_written: u64,
+    ms_writing: u64,
+    ms_busy: u64,
+}
+
+// Counters of each disk from the last call to disk_io(), same as NetPrev
+pub struct DiskIoPrev {
+    disks: HashMap<String, DiskCounters>,
+    time: Instant,
+}
+
+// Whole disks only, as partitions would count the same I/O twice
+// Loop and RAM devices aren't real disks either
+fn disk_counters() -> anyhow::Result<HashMap<String, DiskCounters>> {
+    let stats =
+        std::fs::read_to_string("/proc/diskstats").context("Couldn't read /proc/diskstats")?;
+    let mut disks = HashMap::new();
+    for line in stats.lines() {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let Some(&name) = fields.get(2) else {
+            continue;
+        };
+        if name.starts_with("loop")
+            || name.starts_with("ram")
+            || !std::path::Path::new("/sys/block").join(name).exists()
+        {
+            continue;
+        }
+        let field = |index: usize| {
+            fields
+                .get(index)
+                .and_then(|x| x.parse::<u64>().ok())
+                .with_context(|| format!("Invalid /proc/diskstats line {line}"))
+        };
+        disks.insert(
+            name.to_string(),
+            DiskCounters {
+                reads: field(3)?,
+                sectors_read: field(5)?,
+                ms_reading: field(6)?,
+                writes: field(7)?,
+                sectors_written: field(9)?,
+                ms_writing: field(10)?,
+                ms_busy: field(12)?,
+            },
+        );
+    }
+    Ok(disks)
+}
+
+// Get a baseline for disk_io(), like network_start()
+pub fn disk_io_start() -> DiskIoPrev {
+    DiskIoPrev {
+        disks: disk_counters().unwrap_or_else(|err| {
+            tracing::debug!(
+                "Couldn't get original disk counter data, starting without it: {err:#}"
+            );
+            HashMap::new()
+        }),
+        time: Instant::now(),
+    }
+}
+
+#[instrument(skip_all)]
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+pub fn disk_io(prev_data: &mut DiskIoPrev) -> anyhow::Result<Vec<shared::DiskIoData>> {
+    // Sectors in /proc/diskstats are always 512 bytes, whatever the disk actually uses
+    const SECTOR_SIZE: u64 = 512;
+
+    let counters = disk_counters()?;
+    let elapsed = prev_data.time.elapsed();
+
+    let mut disks = Vec::with_capacity(counters.len());
+    for (name, current) in &counters {
+        // New disks are measured starting with the next call
+        let Some(prev) = prev_data.disks.get(name) else {
+            continue;
+        };
+        let reads = current.reads.saturating_sub(prev.reads);
+        let writes = current.writes.saturating_sub(prev.writes);
+        let ms_waiting = (current.ms_reading + current.ms_writing)
+            .saturating_sub(prev.ms_reading + prev.ms_writing);
+        let ms_busy = current.ms_busy.saturating_sub(prev.ms_busy);
+        disks.push(shared::DiskIoData {
+            name: name.clone(),
+            read_bytes: per_second(
+                current.sectors_read.saturating_sub(prev.sectors_read) * SECTOR_SIZE,
+                elapsed,
+            ),
+            write_bytes: per_second(
+                current.sectors_written.saturating_sub(prev.sectors_written) * SECTOR_SIZE,
+                elapsed,
+            ),
+            read_iops: per_second(reads, elapsed),
+            write_iops: per_second(writes, elapsed),
+            util: if elapsed.is_zero() {
+                0.0
+            } else {
+                round_percent((ms_busy as f64 / elapsed.as_secs_f64() / 10.0).min(100.0) as f32)
+            },
+            // Average time each request took, including time spent queued
+            wait: if reads + writes == 0 {
+                0.0
+            } else {
+                round_percent((ms_waiting as f64 / (reads + writes) as f64) as f32)
+            },
+        });
+    }
+    disks.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+    *prev_data = DiskIoPrev {
+        disks: counters,
+        time: Instant::now(),
+    };
+
+    Ok(disks)
 }
 
 struct UnwrappedProcess {
@@ -191,6 +733,15 @@ pub async fn dpsoftware(
     Ok((uninstalled_list, installed_list))
 }
 
+pub async fn apt_upgrades() -> anyhow::Result<u32> {
+    fs::read_to_string("/run/dietpi/.apt_updates")
+        .await
+        .unwrap_or_else(|_| 0.to_string())
+        .trim_end_matches('\n')
+        .parse::<u32>()
+        .context("Couldn't parse number of APT updates")
+}
+
 #[instrument]
 pub async fn host() -> anyhow::Result<shared::HostData> {
     let info = host::info();
@@ -210,12 +761,7 @@ pub async fn host() -> anyhow::Result<shared::HostData> {
         .into_iter()
         .filter(|x| *x == b'\n')
         .count();
-    let upgradable_pkgs = fs::read_to_string("/run/dietpi/.apt_updates")
-        .await
-        .unwrap_or_else(|_| 0.to_string())
-        .trim_end_matches('\n')
-        .parse::<u32>()
-        .context("Couldn't parse number of APT updates")?;
+    let upgradable_pkgs = apt_upgrades().await?;
     let mut arch = info.architecture().as_str();
     if arch == "unknown" {
         arch = "armv6l/other";
@@ -429,10 +975,151 @@ pub async fn browser_dir(path: &std::path::Path) -> anyhow::Result<Vec<shared::B
     Ok(file_list)
 }
 
+fn read_value(path: &std::path::Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+}
+
+// Sorted, so that sensors don't switch places between samples
+fn sorted_entries(dir: &str, prefix: &str) -> Vec<std::path::PathBuf> {
+    let mut entries = std::fs::read_dir(dir)
+        .map(|dir| {
+            dir.flatten()
+                .map(|x| x.path())
+                .filter(|x| {
+                    x.file_name()
+                        .is_some_and(|x| x.to_string_lossy().starts_with(prefix))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    entries.sort_unstable();
+    entries
+}
+
+// Sysfs has millidegrees Celsius, this gives the configured unit
+#[allow(clippy::cast_possible_truncation)]
+fn sysfs_temp(path: &std::path::Path) -> Option<f32> {
+    let temp = psutil::Temperature::new(read_value(path)?.parse::<f64>().ok()? / 1000.0);
+    let temp = match shared::CONFIG.temp_unit {
+        shared::TempUnit::Celsius => temp.celsius(),
+        shared::TempUnit::Fahrenheit => temp.fahrenheit(),
+    };
+    Some(((temp * 10.0).round() / 10.0) as f32)
+}
+
+fn hwmon_sensors(temps: &mut Vec<shared::TempSensor>, fans: &mut Vec<shared::FanData>) {
+    for hwmon in sorted_entries("/sys/class/hwmon", "hwmon") {
+        let name = read_value(&hwmon.join("name")).unwrap_or_default();
+        for input in sorted_entries(&hwmon.to_string_lossy(), "") {
+            let Some(file) = input.file_name().map(|x| x.to_string_lossy().to_string()) else {
+                continue;
+            };
+            let Some(prefix) = file.strip_suffix("_input") else {
+                continue;
+            };
+            let label = read_value(&hwmon.join(format!("{prefix}_label")));
+            if prefix.starts_with("temp") {
+                let Some(current) = sysfs_temp(&input) else {
+                    continue;
+                };
+                temps.push(shared::TempSensor {
+                    source: "hwmon",
+                    name: name.clone(),
+                    label,
+                    current,
+                    high: sysfs_temp(&hwmon.join(format!("{prefix}_max"))),
+                    critical: sysfs_temp(&hwmon.join(format!("{prefix}_crit"))),
+                });
+            } else if prefix.starts_with("fan") {
+                let Some(rpm) = read_value(&input).and_then(|x| x.parse::<u32>().ok()) else {
+                    continue;
+                };
+                fans.push(shared::FanData {
+                    name: name.clone(),
+                    label,
+                    rpm,
+                });
+            }
+        }
+    }
+}
+
+fn thermal_zone_sensors(temps: &mut Vec<shared::TempSensor>) {
+    for zone in sorted_entries("/sys/class/thermal", "thermal_zone") {
+        let Some(current) = sysfs_temp(&zone.join("temp")) else {
+            continue;
+        };
+        let mut high = None;
+        let mut critical = None;
+        for trip in sorted_entries(&zone.to_string_lossy(), "trip_point_") {
+            let Some(prefix) = trip
+                .file_name()
+                .and_then(|x| x.to_str()?.strip_suffix("_type").map(str::to_string))
+            else {
+                continue;
+            };
+            let temp = || sysfs_temp(&zone.join(format!("{prefix}_temp")));
+            match read_value(&trip).as_deref() {
+                Some("critical") => critical = critical.or_else(temp),
+                // "hot" is meant to be the high threshold, but plenty of boards only have "passive" (where throttling starts)
+                Some("hot") => high = temp().or(high),
+                Some("passive") => high = high.or_else(temp),
+                _ => {}
+            }
+        }
+        temps.push(shared::TempSensor {
+            source: "thermal_zone",
+            name: read_value(&zone.join("type")).unwrap_or_default(),
+            label: None,
+            current,
+            high,
+            critical,
+        });
+    }
+}
+
+// Only on Raspberry Pis, from the firmware
+fn throttled() -> Option<shared::ThrottledData> {
+    let flags = u32::from_str_radix(
+        read_value(std::path::Path::new(
+            "/sys/devices/platform/soc/soc:firmware/get_throttled",
+        ))?
+        .trim_start_matches("0x"),
+        16,
+    )
+    .ok()?;
+    let flag = |bit: u32| flags & (1 << bit) != 0;
+    Some(shared::ThrottledData {
+        under_voltage: flag(0),
+        freq_capped: flag(1),
+        throttled: flag(2),
+        soft_temp_limit: flag(3),
+        under_voltage_occurred: flag(16),
+        freq_capped_occurred: flag(17),
+        throttled_occurred: flag(18),
+        soft_temp_limit_occurred: flag(19),
+    })
+}
+
+// No errors, since any of these could just not exist
 #[instrument]
+pub fn sensors() -> shared::SensorData {
+    let mut temps = Vec::new();
+    let mut fans = Vec::new();
+    hwmon_sensors(&mut temps, &mut fans);
+    thermal_zone_sensors(&mut temps);
+    shared::SensorData {
+        temps,
+        fans,
+        throttled: throttled(),
+    }
+}
+
 // No error message, as there could just be no temperature sensor
-#[allow(clippy::cast_possible_truncation)]
-pub fn temp() -> shared::CPUTemp {
+pub fn cpu_temperature() -> Option<psutil::Temperature> {
     let temps = sensors::temperatures();
     match &temps.get(
         // Prefer 'coretemp' sensor for Intel CPUs, otherwise fallback to first in list
@@ -442,17 +1129,21 @@ pub fn temp() -> shared::CPUTemp {
             .position(|x| x.unit() == "coretemp")
             .unwrap_or(0),
     ) {
-        Some(Ok(temp)) => {
-            let temp = temp.current();
+        Some(Ok(temp)) => Some(psutil::Temperature::new(temp.current().celsius())),
+        _ => None,
+    }
+}
+
+#[instrument]
+#[allow(clippy::cast_possible_truncation)]
+pub fn temp() -> shared::CPUTemp {
+    shared::CPUTemp {
+        temp: cpu_temperature().map(|temp| {
             let temp = match shared::CONFIG.temp_unit {
                 shared::TempUnit::Celsius => temp.celsius(),
                 shared::TempUnit::Fahrenheit => temp.fahrenheit(),
             };
-
-            shared::CPUTemp {
-                temp: Some(temp.round() as i16),
-            }
-        }
-        _ => shared::CPUTemp { temp: None },
+            temp.round() as i16
+        }),
     }
 }
@@ -0,0 +1,510 @@
+use anyhow::Context;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::time::Instant;
+use tracing::{instrument, Instrument};
+
+use crate::shared::{self, CONFIG};
+use crate::{audit, handle_error, recordings};
+
+// Longest time between checks whether a terminal has been idle for too long
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+pub enum Input {
+    Data(Vec<u8>),
+    Resize { cols: u16, rows: u16 },
+}
+
+struct Viewer {
+    // None without a login
+    user: Option<String>,
+    addr: IpAddr,
+    since: u64,
+}
+
+struct Terminal {
+    name: String,
+    // None without a login
+    owner: Option<String>,
+    addr: IpAddr,
+    created: u64,
+    input: mpsc::Sender<Input>,
+    output: broadcast::Sender<Vec<u8>>,
+    scrollback: VecDeque<u8>,
+    // Bytes of output so far, so clients can tell which part of it they've missed
+    end: u64,
+    clients: usize,
+    recording: Option<recordings::Recorder>,
+    // When the last client disconnected
+    idle_since: Option<Instant>,
+    // Never sent on, the terminal closes once this is dropped
+    _closed: watch::Sender<()>,
+    // Tokens for watching the terminal without typing into it, and when they expire
+    shares: HashMap<String, u64>,
+    viewers: HashMap<u64, Viewer>,
+    // Replaced to disconnect all viewers when sharing stops
+    unshared: watch::Sender<()>,
+}
+
+// Kept in memory, so restarting the dashboard closes all terminals
+static TERMINALS: LazyLock<Mutex<HashMap<String, Terminal>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+static NEXT_VIEWER: AtomicU64 = AtomicU64::new(0);
+// Sent on whenever terminals or their viewers change, so clients can update their lists
+static CHANGED: LazyLock<watch::Sender<()>> = LazyLock::new(|| watch::channel(()).0);
+
+fn changed() {
+    CHANGED.send_replace(());
+}
+
+pub fn changes() -> watch::Receiver<()> {
+    CHANGED.subscribe()
+}
+
+// Keeps a terminal from going idle, until it's dropped
+pub struct Attached {
+    pub id: String,
+    pub input: mpsc::Sender<Input>,
+}
+
+pub struct Output {
+    // Where in the output the replayed part starts
+    pub offset: u64,
+    pub replay: Vec<u8>,
+    pub receiver: broadcast::Receiver<Vec<u8>>,
+}
+
+// Starts following the output, replaying what came after the given offset
+// Everything that's still in the scrollback is replayed if that part isn't there anymore
+// None once the terminal has closed
+fn output(id: &str, offset: Option<u64>) -> Option<Output> {
+    let terminals = TERMINALS.lock().unwrap_or_else(PoisonError::into_inner);
+    let terminal = terminals.get(id)?;
+    let start = terminal.end - terminal.scrollback.len() as u64;
+    let offset = offset
+        .filter(|x| (start..=terminal.end).contains(x))
+        .unwrap_or(start);
+    let output = Output {
+        offset,
+        replay: terminal
+            .scrollback
+            .iter()
+            .skip(usize::try_from(offset - start).unwrap_or_default())
+            .copied()
+            .collect(),
+        receiver: terminal.output.subscribe(),
+    };
+    drop(terminals);
+    Some(output)
+}
+
+impl Attached {
+    pub fn output(&self, offset: Option<u64>) -> Option<Output> {
+        output(&self.id, offset)
+    }
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        let mut terminals = TERMINALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(terminal) = terminals.get_mut(&self.id) else {
+            return;
+        };
+        terminal.clients -= 1;
+        if terminal.clients == 0 {
+            if CONFIG.terminal_idle_timeout == 0 {
+                terminals.remove(&self.id);
+            } else {
+                terminal.idle_since = Some(Instant::now());
+            }
+        }
+        drop(terminals);
+        changed();
+    }
+}
+
+// Watching a shared terminal, until it's dropped
+pub struct Viewing {
+    pub id: String,
+    pub name: String,
+    viewer: u64,
+    // Finishes when the owner stops sharing
+    pub unshared: watch::Receiver<()>,
+}
+
+impl Viewing {
+    pub fn output(&self, offset: Option<u64>) -> Option<Output> {
+        output(&self.id, offset)
+    }
+}
+
+impl Drop for Viewing {
+    fn drop(&mut self) {
+        if let Some(terminal) = TERMINALS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(&self.id)
+        {
+            terminal.viewers.remove(&self.viewer);
+        }
+        changed();
+    }
+}
+
+fn push(id: &str, data: &[u8]) {
+    let mut terminals = TERMINALS.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(terminal) = terminals.get_mut(id) else {
+        return;
+    };
+    terminal.scrollback.extend(data);
+    let excess = terminal
+        .scrollback
+        .len()
+        .saturating_sub(CONFIG.terminal_scrollback);
+    terminal.scrollback.drain(..excess);
+    terminal.end += data.len() as u64;
+    if let Some(recording) = &terminal.recording {
+        recording.output(data);
+    }
+    // Sent while locked, so attaching clients get everything exactly once
+    // Only fails without any attached clients
+    let _ = terminal.output.send(data.to_vec());
+    drop(terminals);
+}
+
+fn record(id: &str, event: impl FnOnce(&recordings::Recorder)) {
+    if let Some(recording) = TERMINALS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(id)
+        .and_then(|x| x.recording.as_ref())
+    {
+        event(recording);
+    }
+}
+
+// Finishes once nobody has been attached for the idle timeout, or the terminal has been killed
+async fn idle(id: &str) {
+    let timeout = Duration::from_secs(CONFIG.terminal_idle_timeout);
+    let mut interval =
+        tokio::time::interval(IDLE_CHECK_INTERVAL.min(timeout).max(Duration::from_secs(1)));
+    loop {
+        interval.tick().await;
+        if TERMINALS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(id)
+            .is_none_or(|x| x.idle_since.is_some_and(|x| x.elapsed() >= timeout))
+        {
+            return;
+        }
+    }
+}
+
+#[instrument(skip_all, fields(id = %id))]
+async fn run(
+    id: String,
+    pty: pty_process::Pty,
+    pts: pty_process::Pts,
+    mut child: tokio::process::Child,
+    mut input: mpsc::Receiver<Input>,
+    mut closed: watch::Receiver<()>,
+) {
+    let (mut pty_read, mut pty_write) = pty.into_split();
+    tokio::select! {
+        () = async {
+            let mut data = [0; 4096];
+            loop {
+                match pty_read.read(&mut data).await {
+                    Ok(num_read) if num_read > 0 => push(&id, &data[..num_read]),
+                    _ => {
+                        tracing::debug!("Terminal closed, breaking");
+                        break;
+                    }
+                }
+            }
+        }.instrument(tracing::debug_span!("term_reader")) => {}
+        () = async {
+            while let Some(data) = input.recv().await {
+                match data {
+                    Input::Data(data) => {
+                        record(&id, |x| x.input(&data));
+                        if pty_write.write_all(&data).await.is_err() {
+                            tracing::debug!("Terminal closed, breaking");
+                            break;
+                        }
+                    }
+                    Input::Resize { cols, rows } => {
+                        record(&id, |x| x.resize(cols, rows));
+                        handle_error!(pty_write
+                            .resize(pty_process::Size::new(rows, cols))
+                            .context("Couldn't resize pty"));
+                    }
+                }
+            }
+        }.instrument(tracing::debug_span!("term_writer")) => {}
+        _ = closed.changed() => tracing::info!("Terminal was killed"),
+        () = idle(&id) => tracing::info!("Terminal has been idle for too long"),
+    }
+
+    TERMINALS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&id);
+    changed();
+    // Closing the pty hangs up the shell, the halves only close it once both are gone
+    #[allow(clippy::drop_non_drop)]
+    {
+        drop(pty_read);
+        drop(pty_write);
+        drop(pts);
+    }
+    handle_error!(
+        child.wait().await.context("Couldn't close terminal"),
+        return
+    );
+    tracing::info!("Closed terminal");
+}
+
+// Starts a new terminal, with the client already attached to it
+pub fn spawn(
+    actor: &audit::Actor,
+    name: &str,
+    size: Option<(u16, u16)>,
+) -> anyhow::Result<Attached> {
+    let pty = pty_process::Pty::new().context("Couldn't spawn pty")?;
+    // Set before anything runs, so it's known for the recording
+    let (cols, rows) = size.unwrap_or((80, 24));
+    pty.resize(pty_process::Size::new(rows, cols))
+        .context("Couldn't resize pty")?;
+
+    // Without a configured command, log in as terminal_user
+    let (mut cmd, command) = if let Some((program, args)) = CONFIG.terminal_command.split_first() {
+        let mut cmd = pty_process::Command::new(program);
+        cmd.args(args);
+        (cmd, CONFIG.terminal_command.join(" "))
+    } else {
+        let mut cmd = pty_process::Command::new("/bin/login");
+        if CONFIG.terminal_user != "manual" {
+            cmd.args(["-f", &CONFIG.terminal_user]);
+        }
+        (cmd, CONFIG.terminal_user.clone())
+    };
+    cmd.env("TERM", &CONFIG.terminal_term);
+    cmd.envs(&CONFIG.terminal_env);
+    if !CONFIG.terminal_dir.is_empty() {
+        cmd.current_dir(&CONFIG.terminal_dir);
+    }
+    if let Some(gid) = CONFIG.terminal_gid {
+        cmd.gid(gid);
+    }
+    if let Some(uid) = CONFIG.terminal_uid {
+        cmd.uid(uid);
+    }
+
+    let pts = pty.pts().context("Couldn't spawn pts")?;
+
+    let mut buf = [0u8; 16];
+    getrandom::getrandom(&mut buf).context("Couldn't generate random terminal ID")?;
+    let id = hex::encode(buf);
+
+    // Not opening the terminal at all if it can't be recorded
+    let recording = CONFIG
+        .terminal_recording
+        .then(|| recordings::start(&id, name, actor, cols, rows))
+        .transpose()?;
+
+    let child = audit::record(
+        actor,
+        "terminal.open",
+        &[&command, &id],
+        cmd.spawn(&pts).context("Couldn't spawn command onto pts"),
+    )?;
+
+    let (input, input_recv) = mpsc::channel(16);
+    let output = broadcast::channel(256).0;
+    let (closed, closed_recv) = watch::channel(());
+    let attached = Attached {
+        id: id.clone(),
+        input: input.clone(),
+    };
+    TERMINALS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(
+            id.clone(),
+            Terminal {
+                name: name.to_string(),
+                owner: actor.user.clone(),
+                addr: actor.addr,
+                created: jsonwebtoken::get_current_timestamp(),
+                input,
+                output,
+                scrollback: VecDeque::new(),
+                end: 0,
+                clients: 1,
+                recording,
+                idle_since: None,
+                _closed: closed,
+                shares: HashMap::new(),
+                viewers: HashMap::new(),
+                unshared: watch::channel(()).0,
+            },
+        );
+    changed();
+    tokio::spawn(run(id, pty, pts, child, input_recv, closed_recv));
+    Ok(attached)
+}
+
+// Reattaches to a terminal of the same user
+pub fn attach(id: &str, actor: &audit::Actor) -> anyhow::Result<Attached> {
+    let mut terminals = TERMINALS.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = terminals
+        .get_mut(id)
+        .filter(|x| x.owner == actor.user)
+        .context("No such terminal")
+        .map(|terminal| {
+            if let Some(recording) = &terminal.recording {
+                recording.marker(&format!(
+                    "Attached by {} from {}",
+                    actor.user.as_deref().unwrap_or("-"),
+                    actor.addr
+                ));
+            }
+            terminal.clients += 1;
+            terminal.idle_since = None;
+            Attached {
+                id: id.to_string(),
+                input: terminal.input.clone(),
+            }
+        });
+    drop(terminals);
+    changed();
+    audit::record(actor, "terminal.attach", &[id], result)
+}
+
+pub fn rename(id: &str, name: &str) {
+    if let Some(terminal) = TERMINALS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_mut(id)
+    {
+        terminal.name = name.to_string();
+    }
+    changed();
+}
+
+// Creates a token for others to watch the terminal, and returns it with its expiry
+pub fn share(id: &str, actor: &audit::Actor) -> anyhow::Result<(String, u64)> {
+    let mut buf = [0u8; 16];
+    getrandom::getrandom(&mut buf).context("Couldn't generate random share token")?;
+    let token = hex::encode(buf);
+    let now = jsonwebtoken::get_current_timestamp();
+    let expires = now + CONFIG.terminal_share_expiry;
+
+    let mut terminals = TERMINALS.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = terminals
+        .get_mut(id)
+        .filter(|x| x.owner == actor.user)
+        .context("No such terminal")
+        .map(|terminal| {
+            terminal.shares.retain(|_, x| *x > now);
+            terminal.shares.insert(token.clone(), expires);
+            (token, expires)
+        });
+    drop(terminals);
+    audit::record(actor, "terminal.share", &[id], result)
+}
+
+// Makes all share tokens invalid, and disconnects everyone who's watching
+pub fn unshare(id: &str) {
+    if let Some(terminal) = TERMINALS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_mut(id)
+    {
+        terminal.shares.clear();
+        terminal.unshared = watch::channel(()).0;
+    }
+}
+
+// Starts watching the terminal that the share token is for
+pub fn view(token: &str, actor: &audit::Actor) -> anyhow::Result<Viewing> {
+    let now = jsonwebtoken::get_current_timestamp();
+    let viewer = NEXT_VIEWER.fetch_add(1, Ordering::Relaxed);
+    let mut terminals = TERMINALS.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = terminals
+        .iter_mut()
+        .find(|(_, x)| x.shares.get(token).is_some_and(|&x| x > now))
+        .context("Invalid or expired share token")
+        .map(|(id, terminal)| {
+            if let Some(recording) = &terminal.recording {
+                recording.marker(&format!(
+                    "Watched by {} from {}",
+                    actor.user.as_deref().unwrap_or("-"),
+                    actor.addr
+                ));
+            }
+            terminal.viewers.insert(
+                viewer,
+                Viewer {
+                    user: actor.user.clone(),
+                    addr: actor.addr,
+                    since: now,
+                },
+            );
+            Viewing {
+                id: id.clone(),
+                name: terminal.name.clone(),
+                viewer,
+                unshared: terminal.unshared.subscribe(),
+            }
+        });
+    drop(terminals);
+    changed();
+    let id = result.as_ref().map_or("-", |x| x.id.as_str()).to_string();
+    audit::record(actor, "terminal.view", &[id], result)
+}
+
+// Returns whether the terminal existed
+pub fn kill(id: &str) -> bool {
+    let terminal = TERMINALS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(id);
+    changed();
+    terminal.is_some()
+}
+
+pub fn list() -> Vec<shared::TerminalInfo> {
+    let terminals = TERMINALS.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut list = terminals
+        .iter()
+        .map(|(id, x)| shared::TerminalInfo {
+            id: id.clone(),
+            name: x.name.clone(),
+            user: x.owner.clone(),
+            addr: x.addr.to_string(),
+            created: x.created,
+            clients: x.clients,
+            idle: x.idle_since.map(|x| x.elapsed().as_secs()),
+            viewers: x
+                .viewers
+                .values()
+                .map(|x| shared::ViewerInfo {
+                    user: x.user.clone(),
+                    addr: x.addr.to_string(),
+                    since: x.since,
+                })
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+    drop(terminals);
+    list.sort_unstable_by_key(|x| std::cmp::Reverse(x.created));
+    list
+}
@@ -0,0 +1,237 @@
+use anyhow::Context;
+use ring::{digest, hmac};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex, PoisonError};
+use subtle::ConstantTimeEq;
+
+use crate::{config, handle_error};
+
+const FILE: &str = "totp.json";
+// RFC 6238 defaults, which is what authenticator apps expect
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODES: usize = 10;
+
+#[derive(Serialize, Deserialize)]
+struct Enrollment {
+    secret: String,
+    // SHA-256 hashes of the recovery codes that haven't been used yet
+    recovery: Vec<String>,
+}
+
+pub enum Check {
+    Passed,
+    Missing,
+    Invalid,
+}
+
+// Last time step used by each user, so a code can't be used twice
+// Also held while the file is changed, so recovery codes can't be used twice either
+static LAST_STEP: LazyLock<Mutex<HashMap<String, u64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn load() -> anyhow::Result<HashMap<String, Enrollment>> {
+    let path = config::data_path(FILE);
+    match std::fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .with_context(|| format!("Couldn't parse two-factor file {}", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(err) => {
+            Err(err).with_context(|| format!("Couldn't read two-factor file {}", path.display()))
+        }
+    }
+}
+
+fn save(users: &HashMap<String, Enrollment>) -> anyhow::Result<()> {
+    let data = serde_json::to_vec(users).context("Couldn't serialize two-factor data")?;
+    config::write_private(FILE, &data)
+}
+
+fn code_at(secret: &[u8], step: u64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &step.to_be_bytes());
+    let tag = tag.as_ref();
+    // Dynamic truncation from RFC 4226
+    let offset = usize::from(tag[tag.len() - 1] & 0xf);
+    let value = u32::from_be_bytes([
+        tag[offset] & 0x7f,
+        tag[offset + 1],
+        tag[offset + 2],
+        tag[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        value % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+// Allow one step of clock drift either way, and return the step that matched
+fn matching_step(secret: &[u8], code: &str) -> Option<u64> {
+    let now = jsonwebtoken::get_current_timestamp() / STEP;
+    (now.saturating_sub(1)..=now + 1)
+        .find(|step| bool::from(code_at(secret, *step).as_bytes().ct_eq(code.as_bytes())))
+}
+
+fn hash_recovery(code: &str) -> String {
+    let code = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    hex::encode(digest::digest(&digest::SHA256, code.as_bytes()))
+}
+
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|x| {
+            if x.is_ascii_alphanumeric() || b"-._~".contains(&x) {
+                char::from(x).to_string()
+            } else {
+                format!("%{x:02X}")
+            }
+        })
+        .collect()
+}
+
+pub fn enabled(user: &str) -> anyhow::Result<bool> {
+    Ok(load()?.contains_key(user))
+}
+
+// Users without two-factor authentication always pass
+#[allow(clippy::significant_drop_tightening)]
+pub fn check(user: &str, code: &str) -> Check {
+    let mut last_steps = LAST_STEP.lock().unwrap_or_else(PoisonError::into_inner);
+    // Fail closed, since this can't tell whether the user has enrolled
+    let mut users = handle_error!(load(), return Check::Invalid);
+    let Some(enrollment) = users.get_mut(user) else {
+        return Check::Passed;
+    };
+
+    let code = code.trim();
+    if code.is_empty() {
+        return Check::Missing;
+    }
+
+    if code.len() == DIGITS as usize && code.bytes().all(|x| x.is_ascii_digit()) {
+        let secret = handle_error!(
+            hex::decode(&enrollment.secret).context("Invalid two-factor secret"),
+            return Check::Invalid
+        );
+        let last_step = last_steps.entry(user.to_string()).or_default();
+        return match matching_step(&secret, code) {
+            Some(step) if step > *last_step => {
+                *last_step = step;
+                Check::Passed
+            }
+            _ => Check::Invalid,
+        };
+    }
+
+    let hash = hash_recovery(code);
+    let Some(index) = enrollment
+        .recovery
+        .iter()
+        .position(|x| bool::from(x.as_bytes().ct_eq(hash.as_bytes())))
+    else {
+        return Check::Invalid;
+    };
+    enrollment.recovery.remove(index);
+    let left = enrollment.recovery.len();
+    // Only accept the code once it's been removed for good
+    handle_error!(save(&users), return Check::Invalid);
+    tracing::warn!("User {} used a recovery code, {} left", user, left);
+    Check::Passed
+}
+
+pub struct NewEnrollment {
+    secret: Vec<u8>,
+    pub uri: String,
+    pub recovery: Vec<String>,
+}
+
+impl NewEnrollment {
+    pub fn new(user: &str) -> anyhow::Result<Self> {
+        let mut secret = vec![0u8; 20];
+        getrandom::getrandom(&mut secret).context("Couldn't generate random secret")?;
+
+        let recovery = (0..RECOVERY_CODES)
+            .map(|_| {
+                let mut buf = [0u8; 5];
+                getrandom::getrandom(&mut buf).context("Couldn't generate recovery code")?;
+                let code = hex::encode(buf);
+                Ok(format!("{}-{}", &code[..5], &code[5..]))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Include the hostname, so that multiple dashboards can be told apart in the app
+        let host = std::fs::read_to_string("/etc/hostname").unwrap_or_default();
+        let label = match host.trim() {
+            "" => user.to_string(),
+            host => format!("{user}@{host}"),
+        };
+        let uri = format!(
+            "otpauth://totp/DietPi%20Dashboard:{}?secret={}&issuer=DietPi%20Dashboard&algorithm=SHA1&digits={DIGITS}&period={STEP}",
+            uri_encode(&label),
+            data_encoding::BASE32_NOPAD.encode(&secret),
+        );
+
+        Ok(Self {
+            secret,
+            uri,
+            recovery,
+        })
+    }
+
+    pub fn verify(&self, code: &str) -> bool {
+        matching_step(&self.secret, code.trim()).is_some()
+    }
+
+    // Replaces any earlier enrollment of the same user
+    pub fn save(self, user: &str) -> anyhow::Result<()> {
+        let _guard = LAST_STEP.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut users = load()?;
+        users.insert(
+            user.to_string(),
+            Enrollment {
+                secret: hex::encode(&self.secret),
+                recovery: self.recovery.iter().map(|x| hash_recovery(x)).collect(),
+            },
+        );
+        save(&users)
+    }
+}
+
+// Returns whether the user had two-factor authentication enabled
+pub fn remove(user: &str) -> anyhow::Result<bool> {
+    let _guard = LAST_STEP.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut users = load()?;
+    if users.remove(user).is_none() {
+        return Ok(false);
+    }
+    save(&users)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SHA-1 test vectors from RFC 6238 appendix B, which lists 8 digits of each code
+    #[test]
+    fn rfc6238_codes() {
+        let secret = b"12345678901234567890";
+        for (time, code) in [
+            (59, "94287082"),
+            (1_111_111_109, "07081804"),
+            (1_111_111_111, "14050471"),
+            (1_234_567_890, "89005924"),
+            (2_000_000_000, "69279037"),
+            (20_000_000_000, "65353130"),
+        ] {
+            assert_eq!(code_at(secret, time / STEP), code[2..], "time {time}");
+        }
+    }
+}